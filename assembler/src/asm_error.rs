@@ -0,0 +1,19 @@
+use std::fmt;
+
+// Where and why assembly failed. Lines and columns count from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub reason: String,
+}
+
+// Written out by hand, since the workspace's core crate hides the one thiserror's derive expects
+impl std::error::Error for AsmError {}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.reason)
+    }
+}
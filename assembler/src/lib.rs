@@ -0,0 +1,342 @@
+mod asm_error;
+mod parse;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use core::{Instruction, START_ADDRESS};
+
+use crate::parse::{Expr, Line, Location, Operand, Term};
+
+pub use crate::asm_error::AsmError;
+
+const MAX_INCLUDE_DEPTH: usize = 16;
+const MAX_CONSTANT_DEPTH: usize = 64;
+
+enum Symbol {
+    Label(i64),
+    Constant(Expr),
+}
+
+enum Item {
+    Instruction { mnemonic: String, operands: Vec<Operand> },
+    Bytes(Vec<Expr>),
+    Words(Vec<Expr>),
+    Raw(Vec<u8>),
+}
+
+// Assembly happens in two passes. The first reads every line, giving each label the address it ends up at,
+// and the second encodes the items once every label and constant is known
+#[derive(Default)]
+struct Assembler {
+    items: Vec<(Item, Location)>,
+    symbols: HashMap<String, Symbol>,
+    size: usize,
+}
+
+/// Assemble classic CHIP-8 mnemonics into a ROM that loads at 0x200.
+/// path names the source in errors, and included files are found relative to it.
+pub fn assemble(source: &str, path: &Path) -> Result<Vec<u8>, AsmError> {
+    let mut assembler = Assembler::default();
+    assembler.read(source, path, 0)?;
+    assembler.emit()
+}
+
+impl Assembler {
+    fn read(&mut self, source: &str, path: &Path, depth: usize) -> Result<(), AsmError> {
+        let file: Rc<str> = path.display().to_string().into();
+
+        for (index, text) in source.lines().enumerate() {
+            let line = Line::new(text, file.clone(), index + 1);
+            let (labels, keyword, rest) = line.split();
+
+            for label in labels {
+                let address = START_ADDRESS as i64 + self.size as i64;
+                self.define(line.name(label)?, Symbol::Label(address), line.location(label))?;
+            }
+
+            let Some(keyword) = keyword else { continue };
+            let location = line.location(keyword);
+            let mnemonic = keyword.to_ascii_uppercase();
+
+            let item = match mnemonic.as_str() {
+                "DEFINE" => {
+                    let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+                    let name = line.name(&rest[..end])?;
+                    let value = line.expr(&rest[end..])?;
+                    self.define(name, Symbol::Constant(value), line.location(&rest[..end]))?;
+                    continue;
+                },
+                "INCLUDE" => {
+                    let name = line.quoted(rest)?;
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(location.error("includes are nested too deeply"));
+                    }
+                    let include_path = path.parent().unwrap_or(Path::new("")).join(name);
+                    let source = fs::read_to_string(&include_path)
+                        .map_err(|err| line.location(rest).error(format!("can't read {}: {}", include_path.display(), err)))?;
+                    self.read(&source, &include_path, depth + 1)?;
+                    continue;
+                },
+                "DB" => Item::Bytes(line.operands(rest).into_iter().map(|value| line.expr(value)).collect::<Result<_, _>>()?),
+                "DW" => Item::Words(line.operands(rest).into_iter().map(|value| line.expr(value)).collect::<Result<_, _>>()?),
+                "SPRITE" => Item::Raw(line.sprite_row(rest)?),
+                _ => {
+                    let mut operands = line.operands(rest);
+                    // SAVE and LOAD take a register range, e.g. SAVE V1 - V3
+                    if (mnemonic == "SAVE" || mnemonic == "LOAD") && operands.len() == 1 {
+                        if let Some((first, last)) = operands[0].split_once('-') {
+                            operands = vec![first.trim(), last.trim()];
+                        }
+                    }
+                    let operands = operands.into_iter().map(|operand| line.operand(operand)).collect::<Result<_, _>>()?;
+                    Item::Instruction { mnemonic, operands }
+                },
+            };
+
+            self.size += match &item {
+                Item::Instruction { operands, .. } if matches!(operands.get(1), Some(Operand::Long(_))) => 4,
+                Item::Instruction { .. } => 2,
+                Item::Bytes(values) => values.len(),
+                Item::Words(values) => values.len() * 2,
+                Item::Raw(bytes) => bytes.len(),
+            };
+            self.items.push((item, location));
+        }
+
+        Ok(())
+    }
+
+    fn define(&mut self, name: String, symbol: Symbol, location: Location) -> Result<(), AsmError> {
+        if self.symbols.contains_key(&name) {
+            return Err(location.error(format!("'{}' is already defined", name)));
+        }
+        self.symbols.insert(name, symbol);
+        Ok(())
+    }
+
+    fn emit(&self) -> Result<Vec<u8>, AsmError> {
+        let mut rom = Vec::with_capacity(self.size);
+
+        for (item, location) in &self.items {
+            match item {
+                Item::Instruction { mnemonic, operands } => {
+                    for word in self.encode(mnemonic, operands, location)? {
+                        rom.extend(word.to_be_bytes());
+                    }
+                },
+                Item::Bytes(values) => {
+                    for value in values {
+                        rom.push(self.value(value, -0x80, 0xFF)? as u8);
+                    }
+                },
+                Item::Words(values) => {
+                    for value in values {
+                        rom.extend((self.value(value, -0x8000, 0xFFFF)? as u16).to_be_bytes());
+                    }
+                },
+                Item::Raw(bytes) => rom.extend(bytes),
+            }
+        }
+
+        Ok(rom)
+    }
+
+    fn encode(&self, mnemonic: &str, operands: &[Operand], location: &Location) -> Result<Vec<u16>, AsmError> {
+        use Operand::*;
+
+        let address = |value| self.value(value, 0, 0xFFF).map(|value| value as u16);
+        let byte = |value| self.value(value, -0x80, 0xFF).map(|value| value as u8);
+        let nibble = |value| self.value(value, 0, 0xF).map(|value| value as u8);
+
+        let instruction = match (mnemonic, operands) {
+            ("CLS", []) => Instruction::Cls,
+            ("RET", []) => Instruction::Ret,
+            ("SCD", [Value(n)]) => Instruction::ScrollDown(nibble(n)?),
+            ("SCU", [Value(n)]) => Instruction::ScrollUp(nibble(n)?),
+            ("SCR", []) => Instruction::ScrollRight,
+            ("SCL", []) => Instruction::ScrollLeft,
+            ("EXIT", []) => Instruction::Exit,
+            ("LOW", []) => Instruction::Lores,
+            ("HIGH", []) => Instruction::Hires,
+            ("SYS", [Value(nnn)]) => Instruction::Sys(address(nnn)?),
+            ("JP", [Value(nnn)]) => Instruction::Jp(address(nnn)?),
+            ("JP", [Register(0), Value(nnn)]) => Instruction::JpV0(address(nnn)?),
+            ("CALL", [Value(nnn)]) => Instruction::Call(address(nnn)?),
+            ("SE", [Register(x), Value(nn)]) => Instruction::SeByte { x: *x, nn: byte(nn)? },
+            ("SE", [Register(x), Register(y)]) => Instruction::SeReg { x: *x, y: *y },
+            ("SNE", [Register(x), Value(nn)]) => Instruction::SneByte { x: *x, nn: byte(nn)? },
+            ("SNE", [Register(x), Register(y)]) => Instruction::SneReg { x: *x, y: *y },
+            ("SAVE", [Register(x), Register(y)]) => Instruction::SaveRange { x: *x, y: *y },
+            ("LOAD", [Register(x), Register(y)]) => Instruction::LoadRange { x: *x, y: *y },
+            ("LD", [Register(x), Value(nn)]) => Instruction::LdByte { x: *x, nn: byte(nn)? },
+            ("LD", [Register(x), Register(y)]) => Instruction::LdReg { x: *x, y: *y },
+            ("LD", [I, Value(nnn)]) => Instruction::LdI(address(nnn)?),
+            ("LD", [I, Long(value)]) => {
+                return Ok(vec![Instruction::LongI.encode(), self.value(value, 0, 0xFFFF)? as u16]);
+            },
+            ("LD", [Register(x), Dt]) => Instruction::LdVxDt(*x),
+            ("LD", [Register(x), K]) => Instruction::LdVxK(*x),
+            ("LD", [Dt, Register(x)]) => Instruction::LdDtVx(*x),
+            ("LD", [St, Register(x)]) => Instruction::LdStVx(*x),
+            ("LD", [F, Register(x)]) => Instruction::LdF(*x),
+            ("LD", [Hf, Register(x)]) => Instruction::LdHf(*x),
+            ("LD", [B, Register(x)]) => Instruction::LdB(*x),
+            ("LD", [IndirectI, Register(x)]) => Instruction::LdIVx(*x),
+            ("LD", [Register(x), IndirectI]) => Instruction::LdVxI(*x),
+            ("LD", [R, Register(x)]) => Instruction::LdRVx(*x),
+            ("LD", [Register(x), R]) => Instruction::LdVxR(*x),
+            ("ADD", [Register(x), Value(nn)]) => Instruction::AddByte { x: *x, nn: byte(nn)? },
+            ("ADD", [Register(x), Register(y)]) => Instruction::AddReg { x: *x, y: *y },
+            ("ADD", [I, Register(x)]) => Instruction::AddI(*x),
+            ("OR", [Register(x), Register(y)]) => Instruction::Or { x: *x, y: *y },
+            ("AND", [Register(x), Register(y)]) => Instruction::And { x: *x, y: *y },
+            ("XOR", [Register(x), Register(y)]) => Instruction::Xor { x: *x, y: *y },
+            ("SUB", [Register(x), Register(y)]) => Instruction::Sub { x: *x, y: *y },
+            ("SUBN", [Register(x), Register(y)]) => Instruction::Subn { x: *x, y: *y },
+            // The one operand forms shift vx in place
+            ("SHR", [Register(x)]) => Instruction::Shr { x: *x, y: *x },
+            ("SHR", [Register(x), Register(y)]) => Instruction::Shr { x: *x, y: *y },
+            ("SHL", [Register(x)]) => Instruction::Shl { x: *x, y: *x },
+            ("SHL", [Register(x), Register(y)]) => Instruction::Shl { x: *x, y: *y },
+            ("RND", [Register(x), Value(nn)]) => Instruction::Rnd { x: *x, nn: byte(nn)? },
+            ("DRW", [Register(x), Register(y), Value(n)]) => Instruction::Drw { x: *x, y: *y, n: nibble(n)? },
+            ("SKP", [Register(x)]) => Instruction::Skp(*x),
+            ("SKNP", [Register(x)]) => Instruction::Sknp(*x),
+            ("PLANE", [Value(n)]) => Instruction::Plane(nibble(n)?),
+            ("AUDIO", []) => Instruction::Audio,
+            ("PITCH", [Register(x)]) => Instruction::Pitch(*x),
+            (mnemonic, _) if MNEMONICS.contains(&mnemonic) => {
+                return Err(location.error(format!("invalid operands for {}", mnemonic)));
+            },
+            (mnemonic, _) => return Err(location.error(format!("unknown instruction '{}'", mnemonic))),
+        };
+
+        Ok(vec![instruction.encode()])
+    }
+
+    // Evaluate an expression that has to be between min and max
+    fn value(&self, expr: &Expr, min: i64, max: i64) -> Result<i64, AsmError> {
+        let value = self.evaluate(expr, 0)?;
+        if value < min || value > max {
+            return Err(expr.terms[0].2.error(format!("{} doesn't fit, expected {} to 0x{:X}", value, min, max)));
+        }
+        Ok(value)
+    }
+
+    fn evaluate(&self, expr: &Expr, depth: usize) -> Result<i64, AsmError> {
+        let mut total: i64 = 0;
+        for (negative, term, location) in &expr.terms {
+            let value = match term {
+                Term::Number(number) => *number,
+                Term::Symbol(name) => match self.symbols.get(name) {
+                    Some(Symbol::Label(address)) => *address,
+                    Some(Symbol::Constant(_)) if depth >= MAX_CONSTANT_DEPTH => {
+                        return Err(location.error(format!("'{}' depends on itself", name)));
+                    },
+                    Some(Symbol::Constant(value)) => self.evaluate(value, depth + 1)?,
+                    None => return Err(location.error(format!("unknown label or constant '{}'", name))),
+                },
+            };
+            total = if *negative { total.wrapping_sub(value) } else { total.wrapping_add(value) };
+        }
+        Ok(total)
+    }
+}
+
+const MNEMONICS: [&str; 32] = [
+    "CLS", "RET", "SCD", "SCU", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SYS", "JP", "CALL", "SE", "SNE", "SAVE", "LOAD",
+    "LD", "ADD", "OR", "AND", "XOR", "SUB", "SUBN", "SHR", "SHL", "RND", "DRW", "SKP", "SKNP", "PLANE", "AUDIO", "PITCH",
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use core::{disassemble, Syntax, CPU};
+
+    fn asm(source: &str) -> Result<Vec<u8>, AsmError> {
+        assemble(source, Path::new("test.asm"))
+    }
+
+    #[test]
+    fn program() {
+        let source = "
+            define COUNT 3          ; loop counter
+            define DIGIT COUNT + 1
+
+            start:  LD V0, COUNT
+                    LD I, digits
+            loop:   ADD V1, DIGIT
+                    ADD V0, -1
+                    SE V0, 0
+                    JP loop
+            end:    JP end
+
+            digits: db 0x12, 0b1010, 7
+                    dw end - start
+                    sprite #..##..#
+        ";
+        let rom = asm(source).unwrap();
+        assert_eq!(rom, [
+            0x60, 0x03, 0xA2, 0x0E, 0x71, 0x04, 0x70, 0xFF, 0x30, 0x00, 0x12, 0x04, 0x12, 0x0C,
+            0x12, 0x0A, 0x07, 0x00, 0x0C, 0x99,
+        ]);
+
+        // Runs as it would from hand-assembled hex
+        let mut cpu = CPU::new();
+        assert!(cpu.load_rom_from_buffer(&rom).is_ok());
+        for _ in 0..20 {
+            assert!(cpu.cycle().is_ok());
+        }
+        assert_eq!(cpu.v_register(1), 12);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source| asm(source).unwrap_err();
+        assert_eq!(error("CLS\n  FOO V0"), AsmError {
+            file: "test.asm".to_string(), line: 2, column: 3, reason: "unknown instruction 'FOO'".to_string(),
+        });
+        assert_eq!(error("LD V0, 0x100").column, 8);
+        assert_eq!(error("DRW V0, V1, 16").column, 13);
+        assert_eq!(error("SKP 3").reason, "invalid operands for SKP");
+        assert_eq!(error("JP nowhere").reason, "unknown label or constant 'nowhere'");
+        assert_eq!(error("a: CLS\na: RET").line, 2);
+        assert_eq!(error("define X Y\ndefine Y X\nJP X").reason, "'X' depends on itself");
+        assert_eq!(error("LD I, 0x1000").reason, "4096 doesn't fit, expected 0 to 0xFFF");
+    }
+
+    #[test]
+    fn include() {
+        let dir = env::temp_dir().join(format!("chip8-asm-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("font.asm"), "font: sprite ########\n  JP missing").unwrap();
+
+        let main = dir.join("main.asm");
+        let error = assemble("LD I, font\ninclude \"font.asm\"", &main).unwrap_err();
+        assert_eq!((error.file, error.line, error.column), (dir.join("font.asm").display().to_string(), 2, 6));
+
+        let rom = assemble("LD I, font\ninclude \"font.asm\"\nmissing: RET", &main).unwrap();
+        assert_eq!(rom, [0xA2, 0x02, 0xFF, 0x12, 0x05, 0x00, 0xEE]);
+
+        assert!(assemble("include \"nope.asm\"", &main).unwrap_err().reason.starts_with("can't read"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // Every word the disassembler lists, instruction or data, assembles back to itself
+    #[test]
+    fn disassembler_round_trip() {
+        for op_code in 0..=u16::MAX {
+            let line = &disassemble(&op_code.to_be_bytes(), START_ADDRESS, Syntax::Classic)[0];
+            assert_eq!(asm(&line.text).unwrap(), op_code.to_be_bytes(), "{}", line.text);
+        }
+
+        let long = [0xF0, 0x00, 0xAB, 0xCD];
+        let line = &disassemble(&long, START_ADDRESS, Syntax::Classic)[0];
+        assert_eq!(asm(&line.text).unwrap(), long);
+        assert_eq!(asm("DB 0x7F").unwrap(), [0x7F]);
+    }
+}
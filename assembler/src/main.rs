@@ -0,0 +1,7 @@
+use core::cli;
+
+const USAGE: &str = "Usage: chip8-asm <path/to/source> [-o <path/to/rom>]";
+
+fn main() {
+    cli::compile_main(USAGE, assembler::assemble);
+}
@@ -0,0 +1,291 @@
+use std::rc::Rc;
+
+use crate::asm_error::AsmError;
+
+// Names that can't be used for labels or constants
+const RESERVED: [&str; 10] = ["I", "DT", "ST", "K", "F", "HF", "B", "R", "LONG", "[I]"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Location {
+    pub file: Rc<str>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    pub fn error(&self, reason: impl Into<String>) -> AsmError {
+        AsmError { file: self.file.to_string(), line: self.line, column: self.column, reason: reason.into() }
+    }
+}
+
+// One line of source, with parts of it sliced out so their columns can be found
+pub(crate) struct Line<'a> {
+    text: &'a str,
+    file: Rc<str>,
+    number: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Term {
+    Number(i64),
+    Symbol(String),
+}
+
+// Numbers, labels and constants added or subtracted, e.g. sprites + 5
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Expr {
+    pub terms: Vec<(bool, Term, Location)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Operand {
+    Register(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    Hf,
+    B,
+    R,
+    Long(Expr),
+    Value(Expr),
+}
+
+impl<'a> Line<'a> {
+    pub fn new(text: &'a str, file: Rc<str>, number: usize) -> Self {
+        Self { text, file, number }
+    }
+
+    // Location of part, which must be a slice of this line
+    pub fn location(&self, part: &str) -> Location {
+        let offset = part.as_ptr() as usize - self.text.as_ptr() as usize;
+        Location { file: self.file.clone(), line: self.number, column: self.text[..offset].chars().count() + 1 }
+    }
+
+    // Split into labels, the mnemonic or directive, and everything after it, leaving out the comment
+    pub fn split(&self) -> (Vec<&'a str>, Option<&'a str>, &'a str) {
+        let mut rest = strip_comment(self.text).trim();
+        let mut labels = Vec::new();
+
+        loop {
+            let end = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+            if end == 0 || !rest[end..].starts_with(':') {
+                break;
+            }
+            labels.push(&rest[..end]);
+            rest = rest[end + 1..].trim_start();
+        }
+
+        if rest.is_empty() {
+            return (labels, None, rest);
+        }
+        let end = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        (labels, Some(&rest[..end]), rest[end..].trim())
+    }
+
+    // Comma separated operands
+    pub fn operands(&self, text: &'a str) -> Vec<&'a str> {
+        if text.is_empty() {
+            return Vec::new();
+        }
+        text.split(',').map(str::trim).collect()
+    }
+
+    pub fn name(&self, text: &str) -> Result<String, AsmError> {
+        let valid = text.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') && text.chars().all(is_name_char);
+        if !valid {
+            return Err(self.location(text).error(format!("invalid name '{}'", text)));
+        }
+        if register(text).is_some() || RESERVED.contains(&text.to_ascii_uppercase().as_str()) {
+            return Err(self.location(text).error(format!("'{}' is reserved", text)));
+        }
+        Ok(text.to_string())
+    }
+
+    pub fn operand(&self, text: &'a str) -> Result<Operand, AsmError> {
+        if let Some(x) = register(text) {
+            return Ok(Operand::Register(x));
+        }
+
+        let operand = match text.to_ascii_uppercase().as_str() {
+            "I" => Operand::I,
+            "[I]" => Operand::IndirectI,
+            "DT" => Operand::Dt,
+            "ST" => Operand::St,
+            "K" => Operand::K,
+            "F" => Operand::F,
+            "HF" => Operand::Hf,
+            "B" => Operand::B,
+            "R" => Operand::R,
+            upper if upper.starts_with("LONG") && text[4..].starts_with(char::is_whitespace) => {
+                Operand::Long(self.expr(text[4..].trim_start())?)
+            },
+            _ => Operand::Value(self.expr(text)?),
+        };
+        Ok(operand)
+    }
+
+    pub fn expr(&self, text: &'a str) -> Result<Expr, AsmError> {
+        let mut terms = Vec::new();
+        let mut rest = text.trim_start();
+        let mut negative = false;
+
+        loop {
+            if let Some(after) = rest.strip_prefix('-') {
+                negative = !negative;
+                rest = after.trim_start();
+                continue;
+            }
+            if terms.is_empty() {
+                rest = rest.strip_prefix('+').unwrap_or(rest).trim_start();
+            }
+
+            let end = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+            let term = &rest[..end];
+            if term.is_empty() {
+                let found = if rest.is_empty() { "nothing".to_string() } else { format!("'{}'", rest) };
+                return Err(self.location(rest).error(format!("expected a value, found {}", found)));
+            }
+            let value = match number(term) {
+                Some(number) => Term::Number(number),
+                None if term.starts_with(|c: char| c.is_ascii_digit()) => {
+                    return Err(self.location(term).error(format!("invalid number '{}'", term)));
+                },
+                None => Term::Symbol(self.name(term)?),
+            };
+            terms.push((negative, value, self.location(term)));
+
+            rest = rest[end..].trim_start();
+            negative = match rest.chars().next() {
+                None => break,
+                Some('+') => false,
+                Some('-') => true,
+                Some(_) => return Err(self.location(rest).error(format!("unexpected '{}'", rest))),
+            };
+            rest = rest[1..].trim_start();
+        }
+
+        Ok(Expr { terms })
+    }
+
+    // Sprite row drawn with # for set pixels and . for clear ones, 8 or 16 pixels wide
+    pub fn sprite_row(&self, text: &str) -> Result<Vec<u8>, AsmError> {
+        if text.len() != 8 && text.len() != 16 {
+            return Err(self.location(text).error("sprite rows must be 8 or 16 pixels wide"));
+        }
+        let mut row: u16 = 0;
+        for (i, c) in text.char_indices() {
+            row = row << 1 | match c {
+                '#' => 1,
+                '.' => 0,
+                _ => return Err(self.location(&text[i..]).error(format!("'{}' in sprite row, use # or .", c))),
+            };
+        }
+        Ok(if text.len() == 8 { vec![row as u8] } else { row.to_be_bytes().to_vec() })
+    }
+
+    // File name in double quotes
+    pub fn quoted(&self, text: &'a str) -> Result<&'a str, AsmError> {
+        match text.strip_prefix('"').and_then(|rest| rest.strip_suffix('"')) {
+            Some(name) if !name.is_empty() => Ok(name),
+            _ => Err(self.location(text).error("expected a file name in double quotes")),
+        }
+    }
+}
+
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..i],
+            _ => (),
+        }
+    }
+    text
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_' || c == '.'
+}
+
+fn register(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('V' | 'v'), Some(x), None) => x.to_digit(16).map(|x| x as u8),
+        _ => None,
+    }
+}
+
+// Decimal, 0x hexadecimal or 0b binary
+fn number(text: &str) -> Option<i64> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(binary) = lower.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2).ok()
+    } else {
+        lower.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str) -> Line<'_> {
+        Line::new(text, "test.asm".into(), 1)
+    }
+
+    #[test]
+    fn split() {
+        let text = "loop: next:  LD V0, 0x10 ; count down";
+        let (labels, keyword, rest) = line(text).split();
+        assert_eq!(labels, ["loop", "next"]);
+        assert_eq!(keyword, Some("LD"));
+        assert_eq!(line(text).operands(rest), ["V0", "0x10"]);
+        assert_eq!(line(text).location(rest).column, 17);
+
+        assert_eq!(line("  ; just a comment").split(), (vec![], None, ""));
+        assert_eq!(line("include \"a;b.asm\"").split().2, "\"a;b.asm\"");
+    }
+
+    #[test]
+    fn operands() {
+        let text = "vA, [i], dt, LONG 0x1234, font + 5 - 0b11";
+        let line = line(text);
+        let operands: Vec<Operand> = line.operands(text).into_iter().map(|op| line.operand(op).unwrap()).collect();
+        assert_eq!(operands[..3], [Operand::Register(0xA), Operand::IndirectI, Operand::Dt]);
+        assert!(matches!(&operands[3], Operand::Long(expr) if expr.terms[0].1 == Term::Number(0x1234)));
+
+        let Operand::Value(expr) = &operands[4] else { panic!("expected a value") };
+        let terms: Vec<(bool, Term, usize)> = expr.terms.iter().map(|(neg, term, loc)| (*neg, term.clone(), loc.column)).collect();
+        assert_eq!(terms, [
+            (false, Term::Symbol("font".to_string()), 27),
+            (false, Term::Number(5), 34),
+            (true, Term::Number(3), 38),
+        ]);
+    }
+
+    #[test]
+    fn bad_values() {
+        let text = "12ab, 5 *, , v0";
+        let line = line(text);
+        let operands = line.operands(text);
+        assert_eq!(line.operand(operands[0]).unwrap_err().column, 1);
+        assert_eq!(line.operand(operands[1]).unwrap_err().column, 9);
+        assert_eq!(line.operand(operands[2]).unwrap_err().reason, "expected a value, found nothing");
+        assert_eq!(line.name(operands[3]).unwrap_err().reason, "'v0' is reserved");
+    }
+
+    #[test]
+    fn sprite_rows() {
+        assert_eq!(line("").sprite_row("#..##..#").unwrap(), [0x99]);
+        assert_eq!(line("").sprite_row("########........").unwrap(), [0xFF, 0x00]);
+        let text = "sprite #.x.....";
+        assert_eq!(line(text).sprite_row(&text[7..]).unwrap_err().column, 10);
+        assert_eq!(line(text).sprite_row(&text[8..]).unwrap_err().column, 9);
+    }
+}
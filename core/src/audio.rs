@@ -0,0 +1,253 @@
+use crate::{CPU, AUDIO_PATTERN_BYTES, DEFAULT_PITCH};
+
+pub const DEFAULT_PITCH_HZ: f32 = 440.0;
+pub const DEFAULT_VOLUME: f32 = 0.25;
+const PATTERN_BITS: f64 = (AUDIO_PATTERN_BYTES * 8) as f64;
+
+// Turns the CPU's sound state into PCM samples.
+// Frontends call update() once per frame from the emulation thread, and render() from their audio callback.
+pub trait AudioSource {
+    /// Copy whatever sound state is needed from the CPU
+    fn update(&mut self, cpu: &CPU);
+
+    /// Fill the buffer with mono samples in the range -1.0 to 1.0
+    fn render(&mut self, buffer: &mut [f32], sample_rate: u32);
+}
+
+// Plain square wave beeper, sounding while the sound timer is non-zero
+#[derive(Debug, Clone)]
+pub struct SquareWave {
+    pub pitch: f32,
+    pub volume: f32,
+    phase: f32,
+    active: bool,
+}
+
+impl SquareWave {
+    pub fn new(pitch: f32, volume: f32) -> Self {
+        Self {
+            pitch,
+            volume,
+            phase: 0.0,
+            active: false,
+        }
+    }
+
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+}
+
+impl Default for SquareWave {
+    fn default() -> Self {
+        Self::new(DEFAULT_PITCH_HZ, DEFAULT_VOLUME)
+    }
+}
+
+impl AudioSource for SquareWave {
+    fn update(&mut self, cpu: &CPU) {
+        self.set_active(cpu.buzzer_active());
+    }
+
+    fn render(&mut self, buffer: &mut [f32], sample_rate: u32) {
+        if !self.active {
+            // Restart the wave at the next beep so every beep sounds the same
+            self.phase = 0.0;
+            buffer.fill(0.0);
+            return;
+        }
+
+        let step = self.pitch / sample_rate as f32;
+        for sample in buffer.iter_mut() {
+            *sample = if self.phase < 0.5 { self.volume } else { -self.volume };
+            self.phase = (self.phase + step) % 1.0;
+        }
+    }
+}
+
+// XO-CHIP pitch register to pattern playback rate in bits per second
+// 64 is 4000 Hz, and every 48 steps doubles or halves the rate
+pub fn pattern_rate(pitch: u8) -> f64 {
+    4000.0 * 2f64.powf((pitch as f64 - 64.0) / 48.0)
+}
+
+// Plays the XO-CHIP 1-bit audio pattern (F002) at the rate set by the pitch register (FX3A)
+// Phase is kept in f64 and only depends on the inputs, so the output is deterministic
+#[derive(Debug, Clone)]
+pub struct PatternPlayer {
+    pub volume: f32,
+    pattern: [u8; AUDIO_PATTERN_BYTES],
+    pitch: u8,
+    phase: f64,
+    active: bool,
+}
+
+impl PatternPlayer {
+    pub fn new(volume: f32) -> Self {
+        Self {
+            volume,
+            pattern: [0; AUDIO_PATTERN_BYTES],
+            pitch: DEFAULT_PITCH,
+            phase: 0.0,
+            active: false,
+        }
+    }
+
+    /// Start from the CPU's current pattern and pitch, e.g. after a ROM set them or a state was loaded
+    pub fn for_cpu(cpu: &CPU, volume: f32) -> Self {
+        let mut player = Self::new(volume);
+        player.update(cpu);
+        player
+    }
+
+    pub fn set_pattern(&mut self, pattern: [u8; AUDIO_PATTERN_BYTES], pitch: u8) {
+        self.pattern = pattern;
+        self.pitch = pitch;
+    }
+
+    pub fn set_active(&mut self, active: bool) {
+        self.active = active;
+    }
+
+    fn bit(&self, index: usize) -> bool {
+        (self.pattern[index / 8] >> (7 - (index % 8))) & 1 != 0
+    }
+}
+
+impl Default for PatternPlayer {
+    fn default() -> Self {
+        Self::new(DEFAULT_VOLUME)
+    }
+}
+
+impl AudioSource for PatternPlayer {
+    fn update(&mut self, cpu: &CPU) {
+        self.set_pattern(*cpu.audio_pattern(), cpu.pitch());
+        self.set_active(cpu.buzzer_active());
+    }
+
+    fn render(&mut self, buffer: &mut [f32], sample_rate: u32) {
+        if !self.active {
+            self.phase = 0.0;
+            buffer.fill(0.0);
+            return;
+        }
+
+        // Bits of the pattern to advance per output sample
+        let step = pattern_rate(self.pitch) / sample_rate as f64;
+        for sample in buffer.iter_mut() {
+            *sample = if self.bit(self.phase as usize) { self.volume } else { -self.volume };
+            self.phase = (self.phase + step) % PATTERN_BITS;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn square_wave_samples() {
+        let mut wave = SquareWave::new(1000.0, 0.5);
+        let mut buffer = [1.0; 16];
+        wave.render(&mut buffer, 8000);
+        assert_eq!(buffer, [0.0; 16]);
+
+        wave.set_active(true);
+        wave.render(&mut buffer, 8000);
+        let period = [0.5, 0.5, 0.5, 0.5, -0.5, -0.5, -0.5, -0.5];
+        assert_eq!(buffer[0..8], period);
+        assert_eq!(buffer[8..16], period);
+    }
+
+    #[test]
+    fn follows_sound_timer() {
+        let mut cpu = CPU::new();
+        let mut wave = SquareWave::default();
+        let mut buffer = [0.0; 64];
+        cpu.v_register[0] = 2;
+        assert!(cpu.execute(0xF018).is_ok());
+        wave.update(&cpu);
+        wave.render(&mut buffer, 44100);
+        assert!(buffer.iter().any(|s| *s != 0.0));
+        cpu.tick_timers();
+        cpu.tick_timers();
+        wave.update(&cpu);
+        wave.render(&mut buffer, 44100);
+        assert!(buffer.iter().all(|s| *s == 0.0));
+    }
+
+    #[test]
+    fn pattern_rates() {
+        assert_eq!(pattern_rate(64), 4000.0);
+        assert_eq!(pattern_rate(112), 8000.0);
+        assert_eq!(pattern_rate(16), 2000.0);
+    }
+
+    #[test]
+    fn pattern_samples() {
+        let mut pattern = [0; AUDIO_PATTERN_BYTES];
+        pattern[0] = 0xF0;
+        pattern[1] = 0xAA;
+        let mut player = PatternPlayer::new(1.0);
+        player.set_pattern(pattern, 64);
+        player.set_active(true);
+
+        // One bit per sample
+        let mut buffer = [0.0; 16];
+        player.render(&mut buffer, 4000);
+        assert_eq!(buffer, [1.0, 1.0, 1.0, 1.0, -1.0, -1.0, -1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0]);
+
+        // Playback continues where it left off, and wraps around after 128 bits
+        let mut buffer = [0.0; 113];
+        player.render(&mut buffer, 4000);
+        assert!(buffer[..112].iter().all(|s| *s == -1.0));
+        assert_eq!(buffer[112], 1.0);
+
+        // Doubling the pitch at the same sample rate skips every other bit
+        let mut player = PatternPlayer::new(1.0);
+        player.set_pattern(pattern, 112);
+        player.set_active(true);
+        let mut buffer = [0.0; 8];
+        player.render(&mut buffer, 4000);
+        assert_eq!(buffer, [1.0, 1.0, -1.0, -1.0, 1.0, 1.0, 1.0, 1.0]);
+    }
+
+    #[test]
+    fn pattern_from_cpu() {
+        let mut cpu = CPU::with_quirks(crate::Quirks::XO_CHIP);
+        cpu.ram[0x300] = 0x80;
+        cpu.index_register = 0x300;
+        cpu.v_register[0] = 40;
+        cpu.v_register[1] = 1;
+        assert!(cpu.execute(0xF002).is_ok());
+        assert!(cpu.execute(0xF03A).is_ok());
+        assert!(cpu.execute(0xF118).is_ok());
+
+        let mut player = PatternPlayer::new(0.5);
+        player.update(&cpu);
+        let mut buffer = [0.0; 4];
+        player.render(&mut buffer, 44100);
+        assert_eq!(buffer, [0.5, 0.5, 0.5, 0.5]);
+        cpu.tick_timers();
+        player.update(&cpu);
+        player.render(&mut buffer, 44100);
+        assert_eq!(buffer, [0.0; 4]);
+    }
+
+    #[test]
+    fn pattern_player_starts_from_cpu() {
+        let mut cpu = CPU::with_quirks(crate::Quirks::XO_CHIP);
+        cpu.ram[0x300] = 0xF0;
+        cpu.index_register = 0x300;
+        cpu.v_register[0] = 112;
+        assert!(cpu.execute(0xF002).is_ok());
+        assert!(cpu.execute(0xF03A).is_ok());
+
+        let player = PatternPlayer::for_cpu(&cpu, 0.5);
+        assert_eq!(player.pitch, 112);
+        assert_eq!(player.pattern[0], 0xF0);
+        assert!(!player.active);
+        assert_eq!(PatternPlayer::new(0.5).pitch, DEFAULT_PITCH);
+    }
+}
@@ -0,0 +1,47 @@
+use std::{env, fs, process};
+use core::{cli, disassemble, Syntax, START_ADDRESS};
+
+const USAGE: &str = "Usage: chip8-disasm <path/to/rom> [--syntax <classic|octo>] [--base <hex address>]";
+
+fn main() {
+    let mut args: Vec<_> = env::args().collect();
+
+    let syntax = match cli::take_flag(&mut args, "--syntax", USAGE) {
+        Some(name) => match Syntax::from_name(&name) {
+            Some(syntax) => syntax,
+            None => {
+                eprintln!("Unknown syntax: {}", name);
+                process::exit(1);
+            }
+        },
+        None => Syntax::default(),
+    };
+
+    let base = match cli::take_flag(&mut args, "--base", USAGE) {
+        Some(base) => match u16::from_str_radix(base.trim_start_matches("0x"), 16) {
+            Ok(base) => base,
+            Err(err) => {
+                eprintln!("Failed to parse base address: {}", err);
+                process::exit(1);
+            }
+        },
+        None => START_ADDRESS,
+    };
+
+    if args.len() != 2 {
+        eprintln!("{}", USAGE);
+        process::exit(1);
+    }
+
+    let rom = match fs::read(&args[1]) {
+        Ok(rom) => rom,
+        Err(err) => {
+            eprintln!("Error reading ROM: {}", err);
+            process::exit(1);
+        }
+    };
+
+    for line in disassemble(&rom, base, syntax) {
+        println!("{}", line);
+    }
+}
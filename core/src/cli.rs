@@ -0,0 +1,52 @@
+// Argument handling shared by the command line tools
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::{env, fs, process};
+
+// Print a message and exit with an error status
+pub fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+// Remove a "--flag value" pair from the arguments, returning the value if the flag was given
+pub fn take_flag(args: &mut Vec<String>, flag: &str, usage: &str) -> Option<String> {
+    let i = args.iter().position(|arg| arg == flag)?;
+    if i + 1 >= args.len() {
+        fail(usage);
+    }
+    let value = args.remove(i + 1);
+    args.remove(i);
+    Some(value)
+}
+
+// Remove a flag that takes no value, returning whether it was given
+pub fn take_switch(args: &mut Vec<String>, flag: &str) -> bool {
+    match args.iter().position(|arg| arg == flag) {
+        Some(i) => {
+            args.remove(i);
+            true
+        },
+        None => false,
+    }
+}
+
+// Main for "<tool> <path/to/source> [-o <path/to/rom>]": compile the source and write the ROM,
+// next to the source by default, e.g. game.8o becomes game.ch8
+pub fn compile_main<E: Display>(usage: &str, compile: fn(&str, &Path) -> Result<Vec<u8>, E>) {
+    let mut args: Vec<_> = env::args().collect();
+    let output = take_flag(&mut args, "-o", usage);
+
+    if args.len() != 2 {
+        fail(usage);
+    }
+
+    let path = Path::new(&args[1]);
+    let source = fs::read_to_string(path).unwrap_or_else(|err| fail(&format!("Error reading source: {}", err)));
+    let rom = compile(&source, path).unwrap_or_else(|err| fail(&err.to_string()));
+
+    let output = output.map(PathBuf::from).unwrap_or_else(|| path.with_extension("ch8"));
+    if let Err(err) = fs::write(&output, rom) {
+        fail(&format!("Error writing ROM: {}", err));
+    }
+}
@@ -21,6 +21,7 @@ impl std::fmt::Display for CoreError {
 
 use thiserror::Error;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum CoreError {
     #[error("invalid ROM size")]
@@ -29,10 +30,24 @@ pub enum CoreError {
     ProgramCounterError { index: u16 },
     #[error("index register out of bounds\n index: {index}")]
     IndexRegisterError { index: u16 },
+    #[error("memory address out of bounds\n address: {address}")]
+    MemoryError { address: usize },
     #[error("invalid opcode: {opcode}")]
     OpcodeError { opcode: u16 },
     #[error("cannot pop from empty stack")]
     StackEmptyError,
+    #[error("cannot push to full stack")]
+    StackFullError,
     #[error("invalid keypress index: {key}")]
     KeyIndexError { key: usize },
+    #[error("failed to access RPL flag storage: {source}")]
+    FlagStorageError { source: std::io::Error },
+    #[error("invalid save state: {reason}")]
+    StateError { reason: String },
+    #[error("invalid movie file at line {line}: {reason}")]
+    MovieError { line: usize, reason: String },
+    #[error("movie was recorded with a different ROM\n expected hash: {expected:016x}\n found hash: {found:016x}")]
+    RomHashError { expected: u64, found: u64 },
+    #[error("replay went out of sync, the final state doesn't match the recording after {instructions} instructions")]
+    DesyncError { instructions: u64 },
 }
\ No newline at end of file
@@ -0,0 +1,147 @@
+use crate::core_error::CoreError;
+use crate::scheduler::{InstructionHook, Scheduler};
+use crate::{Instruction, CPU};
+
+// Where a debugger run stops. Only the stack depth and program counter are checked,
+// so a subroutine that never returns runs until something else stops it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Breakpoint {
+    /// After the next instruction
+    Step,
+    /// Once the program counter reaches address with the stack as deep as it was
+    StepOver { address: u16, depth: usize },
+    /// Once the stack is shallower than depth
+    Return { depth: usize },
+}
+
+impl Breakpoint {
+    /// Run a CALL at the program counter through to its return, or step anything else
+    pub fn step_over(cpu: &CPU) -> Self {
+        let pc = cpu.program_counter();
+        let op_code = match cpu.memory().get(pc as usize..pc as usize + 2) {
+            Some(&[high, low]) => (high as u16) << 8 | low as u16,
+            _ => return Breakpoint::Step,
+        };
+        match Instruction::decode(op_code) {
+            Ok(Instruction::Call(_)) => Breakpoint::StepOver { address: pc.wrapping_add(2), depth: cpu.stack().len() },
+            _ => Breakpoint::Step,
+        }
+    }
+
+    /// Run until the current subroutine returns, or None outside of one
+    pub fn run_to_return(cpu: &CPU) -> Option<Self> {
+        match cpu.stack().len() {
+            0 => None,
+            depth => Some(Breakpoint::Return { depth }),
+        }
+    }
+
+    /// Checked after each instruction that actually ran, display wait stalls don't count
+    pub fn reached(&self, cpu: &CPU) -> bool {
+        match *self {
+            Breakpoint::Step => true,
+            Breakpoint::StepOver { address, depth } => cpu.program_counter() == address && cpu.stack().len() == depth,
+            Breakpoint::Return { depth } => cpu.stack().len() < depth,
+        }
+    }
+
+    /// Step the scheduler until an instruction stops on the breakpoint, or max_ticks timer ticks have run
+    /// Returns true if the breakpoint was reached
+    pub fn run(&self, scheduler: &mut Scheduler, cpu: &mut CPU, before_instruction: InstructionHook, max_ticks: u64) -> Result<bool, CoreError> {
+        let start = scheduler.ticks_run();
+        // A display wait stall ends on the next tick, which shouldn't use up the budget before anything runs
+        let max_ticks = max_ticks + cpu.waiting_for_vblank() as u64;
+        while scheduler.ticks_run() - start < max_ticks {
+            let instructions = scheduler.instructions_run();
+            scheduler.step(cpu, before_instruction)?;
+            if scheduler.instructions_run() > instructions && self.reached(cpu) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scheduler::no_hook;
+    use crate::{Quirks, START_ADDRESS};
+
+    // Call a subroutine that counts v0 up to 3 before returning, then loop forever
+    fn calling_cpu() -> CPU {
+        let rom = [
+            0x22, 0x04, // 200: CALL 0x204
+            0x12, 0x02, // 202: JP 0x202
+            0x70, 0x01, // 204: ADD V0, 1
+            0x30, 0x03, // 206: SE V0, 3
+            0x12, 0x04, // 208: JP 0x204
+            0x00, 0xEE, // 20A: RET
+        ];
+        let mut cpu = CPU::new();
+        assert!(cpu.load_rom_from_buffer(&rom).is_ok());
+        cpu
+    }
+
+    #[test]
+    fn step() {
+        let mut cpu = calling_cpu();
+        let mut scheduler = Scheduler::new(700);
+        assert!(Breakpoint::Step.run(&mut scheduler, &mut cpu, &mut no_hook, 1).unwrap());
+        assert_eq!(cpu.program_counter(), START_ADDRESS + 4);
+        assert_eq!(scheduler.instructions_run(), 1);
+    }
+
+    #[test]
+    fn step_over() {
+        let mut cpu = calling_cpu();
+        let mut scheduler = Scheduler::new(700);
+        let breakpoint = Breakpoint::step_over(&cpu);
+        assert_eq!(breakpoint, Breakpoint::StepOver { address: START_ADDRESS + 2, depth: 0 });
+        assert!(breakpoint.run(&mut scheduler, &mut cpu, &mut no_hook, 1).unwrap());
+        assert_eq!(cpu.program_counter(), START_ADDRESS + 2);
+        assert_eq!(cpu.v_register(0), 3);
+        // Anything but a call is a single step
+        assert_eq!(Breakpoint::step_over(&cpu), Breakpoint::Step);
+    }
+
+    #[test]
+    fn run_to_return() {
+        let mut cpu = calling_cpu();
+        let mut scheduler = Scheduler::new(700);
+        assert_eq!(Breakpoint::run_to_return(&cpu), None);
+        assert!(Breakpoint::Step.run(&mut scheduler, &mut cpu, &mut no_hook, 1).unwrap());
+        let breakpoint = Breakpoint::run_to_return(&cpu).unwrap();
+        assert!(breakpoint.run(&mut scheduler, &mut cpu, &mut no_hook, 1).unwrap());
+        assert_eq!(cpu.program_counter(), START_ADDRESS + 2);
+        assert!(cpu.stack().is_empty());
+    }
+
+    #[test]
+    fn step_past_display_wait() {
+        // Draw, then add 1 to v0
+        let mut cpu = CPU::with_quirks(Quirks::VIP);
+        assert!(cpu.load_rom_from_buffer(&[0xD0, 0x11, 0x70, 0x01, 0x12, 0x00]).is_ok());
+        let mut scheduler = Scheduler::new(700);
+        assert_eq!(Breakpoint::step_over(&cpu), Breakpoint::Step);
+        assert!(Breakpoint::Step.run(&mut scheduler, &mut cpu, &mut no_hook, 1).unwrap());
+        assert_eq!(cpu.program_counter(), START_ADDRESS + 2);
+        assert!(cpu.waiting_for_vblank());
+
+        // The next step waits out the stall and runs the add
+        assert!(Breakpoint::Step.run(&mut scheduler, &mut cpu, &mut no_hook, 1).unwrap());
+        assert_eq!(cpu.program_counter(), START_ADDRESS + 4);
+        assert_eq!(cpu.v_register(0), 1);
+        assert_eq!(scheduler.instructions_run(), 2);
+    }
+
+    #[test]
+    fn gives_up_after_max_ticks() {
+        let mut cpu = calling_cpu();
+        let mut scheduler = Scheduler::new(60);
+        let breakpoint = Breakpoint::StepOver { address: 0x300, depth: 0 };
+        assert!(!breakpoint.run(&mut scheduler, &mut cpu, &mut no_hook, 2).unwrap());
+        assert_eq!(scheduler.ticks_run(), 2);
+        assert_eq!(scheduler.instructions_run(), 2);
+    }
+}
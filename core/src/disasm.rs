@@ -0,0 +1,249 @@
+use std::fmt;
+
+use crate::instruction::Instruction;
+
+// Mnemonic style for disassembly listings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Syntax {
+    /// Cowgod's technical reference, e.g. LD V3, 0x1F and DRW V0, V1, 5
+    #[default]
+    Classic,
+    /// Octo assembly language, e.g. v3 := 0x1F and sprite v0 v1 5
+    Octo,
+}
+
+impl Syntax {
+    pub fn from_name(name: &str) -> Option<Syntax> {
+        match name.to_ascii_lowercase().as_str() {
+            "classic" => Some(Syntax::Classic),
+            "octo" => Some(Syntax::Octo),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisassemblyLine {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub text: String,
+}
+
+impl fmt::Display for DisassemblyLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes: String = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        write!(f, "{:04X}  {:<8}  {}", self.address, bytes, self.text)
+    }
+}
+
+/// Turn ROM bytes loaded at base_address into one line per instruction.
+/// Words that aren't instructions in any of CHIP-8, SUPER-CHIP or XO-CHIP are listed as data.
+pub fn disassemble(bytes: &[u8], base_address: u16, syntax: Syntax) -> Vec<DisassemblyLine> {
+    let mut lines = Vec::new();
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        let address = base_address.wrapping_add(offset as u16);
+        if offset + 1 >= bytes.len() {
+            // Odd byte left over at the end
+            lines.push(DisassemblyLine { address, bytes: vec![bytes[offset]], text: data(&bytes[offset..], syntax) });
+            break;
+        }
+
+        let op_code = (bytes[offset] as u16) << 8 | bytes[offset + 1] as u16;
+        // XO-CHIP F000 NNNN takes its address from the following word
+        let instruction = Instruction::decode(op_code).ok();
+        let (length, text) = if instruction == Some(Instruction::LongI) && offset + 3 < bytes.len() {
+            let address = (bytes[offset + 2] as u16) << 8 | bytes[offset + 3] as u16;
+            let text = match syntax {
+                Syntax::Classic => format!("LD I, LONG 0x{:04X}", address),
+                Syntax::Octo => format!("i := long 0x{:04X}", address),
+            };
+            (4, text)
+        } else {
+            let text = instruction.and_then(|instruction| match syntax {
+                Syntax::Classic => classic(instruction),
+                Syntax::Octo => octo(instruction),
+            });
+            (2, text.unwrap_or_else(|| data(&bytes[offset..offset + 2], syntax)))
+        };
+
+        lines.push(DisassemblyLine { address, bytes: bytes[offset..offset + length].to_vec(), text });
+        offset += length;
+    }
+
+    lines
+}
+
+fn data(bytes: &[u8], syntax: Syntax) -> String {
+    match (syntax, bytes) {
+        (Syntax::Classic, [high, low]) => format!("DW 0x{:02X}{:02X}", high, low),
+        (Syntax::Classic, _) => format!("DB 0x{:02X}", bytes[0]),
+        (Syntax::Octo, _) => bytes.iter().map(|b| format!("0x{:02X}", b)).collect::<Vec<_>>().join(" "),
+    }
+}
+
+fn classic(instruction: Instruction) -> Option<String> {
+    let text = match instruction {
+        Instruction::ScrollDown(n) => format!("SCD {}", n),
+        Instruction::ScrollUp(n) => format!("SCU {}", n),
+        Instruction::Cls => "CLS".to_string(),
+        Instruction::Ret => "RET".to_string(),
+        Instruction::ScrollRight => "SCR".to_string(),
+        Instruction::ScrollLeft => "SCL".to_string(),
+        Instruction::Exit => "EXIT".to_string(),
+        Instruction::Lores => "LOW".to_string(),
+        Instruction::Hires => "HIGH".to_string(),
+        Instruction::Sys(nnn) if nnn != 0 => format!("SYS 0x{:03X}", nnn),
+        Instruction::Jp(nnn) => format!("JP 0x{:03X}", nnn),
+        Instruction::Call(nnn) => format!("CALL 0x{:03X}", nnn),
+        Instruction::SeByte { x, nn } => format!("SE V{:X}, 0x{:02X}", x, nn),
+        Instruction::SneByte { x, nn } => format!("SNE V{:X}, 0x{:02X}", x, nn),
+        Instruction::SeReg { x, y } => format!("SE V{:X}, V{:X}", x, y),
+        Instruction::SaveRange { x, y } => format!("SAVE V{:X} - V{:X}", x, y),
+        Instruction::LoadRange { x, y } => format!("LOAD V{:X} - V{:X}", x, y),
+        Instruction::LdByte { x, nn } => format!("LD V{:X}, 0x{:02X}", x, nn),
+        Instruction::AddByte { x, nn } => format!("ADD V{:X}, 0x{:02X}", x, nn),
+        Instruction::LdReg { x, y } => format!("LD V{:X}, V{:X}", x, y),
+        Instruction::Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+        Instruction::And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+        Instruction::Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+        Instruction::AddReg { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+        Instruction::Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+        Instruction::Shr { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+        Instruction::Subn { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+        Instruction::Shl { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+        Instruction::SneReg { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+        Instruction::LdI(nnn) => format!("LD I, 0x{:03X}", nnn),
+        Instruction::JpV0(nnn) => format!("JP V0, 0x{:03X}", nnn),
+        Instruction::Rnd { x, nn } => format!("RND V{:X}, 0x{:02X}", x, nn),
+        Instruction::Drw { x, y, n } => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Instruction::Skp(x) => format!("SKP V{:X}", x),
+        Instruction::Sknp(x) => format!("SKNP V{:X}", x),
+        Instruction::Plane(x) => format!("PLANE {}", x),
+        Instruction::Audio => "AUDIO".to_string(),
+        Instruction::LdVxDt(x) => format!("LD V{:X}, DT", x),
+        Instruction::LdVxK(x) => format!("LD V{:X}, K", x),
+        Instruction::LdDtVx(x) => format!("LD DT, V{:X}", x),
+        Instruction::LdStVx(x) => format!("LD ST, V{:X}", x),
+        Instruction::AddI(x) => format!("ADD I, V{:X}", x),
+        Instruction::LdF(x) => format!("LD F, V{:X}", x),
+        Instruction::LdHf(x) => format!("LD HF, V{:X}", x),
+        Instruction::LdB(x) => format!("LD B, V{:X}", x),
+        Instruction::Pitch(x) => format!("PITCH V{:X}", x),
+        Instruction::LdIVx(x) => format!("LD [I], V{:X}", x),
+        Instruction::LdVxI(x) => format!("LD V{:X}, [I]", x),
+        Instruction::LdRVx(x) => format!("LD R, V{:X}", x),
+        Instruction::LdVxR(x) => format!("LD V{:X}, R", x),
+        _ => return None,
+    };
+    Some(text)
+}
+
+fn octo(instruction: Instruction) -> Option<String> {
+    // Octo's conditionals name the case that runs the next instruction, the opposite of the skip
+    let text = match instruction {
+        Instruction::ScrollDown(n) => format!("scroll-down {}", n),
+        Instruction::ScrollUp(n) => format!("scroll-up {}", n),
+        Instruction::Cls => "clear".to_string(),
+        Instruction::Ret => "return".to_string(),
+        Instruction::ScrollRight => "scroll-right".to_string(),
+        Instruction::ScrollLeft => "scroll-left".to_string(),
+        Instruction::Exit => "exit".to_string(),
+        Instruction::Lores => "lores".to_string(),
+        Instruction::Hires => "hires".to_string(),
+        Instruction::Jp(nnn) => format!("jump 0x{:03X}", nnn),
+        Instruction::Call(nnn) => format!(":call 0x{:03X}", nnn),
+        Instruction::SeByte { x, nn } => format!("if v{:x} != 0x{:02X} then", x, nn),
+        Instruction::SneByte { x, nn } => format!("if v{:x} == 0x{:02X} then", x, nn),
+        Instruction::SeReg { x, y } => format!("if v{:x} != v{:x} then", x, y),
+        Instruction::SaveRange { x, y } => format!("save v{:x} - v{:x}", x, y),
+        Instruction::LoadRange { x, y } => format!("load v{:x} - v{:x}", x, y),
+        Instruction::LdByte { x, nn } => format!("v{:x} := 0x{:02X}", x, nn),
+        Instruction::AddByte { x, nn } => format!("v{:x} += 0x{:02X}", x, nn),
+        Instruction::LdReg { x, y } => format!("v{:x} := v{:x}", x, y),
+        Instruction::Or { x, y } => format!("v{:x} |= v{:x}", x, y),
+        Instruction::And { x, y } => format!("v{:x} &= v{:x}", x, y),
+        Instruction::Xor { x, y } => format!("v{:x} ^= v{:x}", x, y),
+        Instruction::AddReg { x, y } => format!("v{:x} += v{:x}", x, y),
+        Instruction::Sub { x, y } => format!("v{:x} -= v{:x}", x, y),
+        Instruction::Shr { x, y } => format!("v{:x} >>= v{:x}", x, y),
+        Instruction::Subn { x, y } => format!("v{:x} =- v{:x}", x, y),
+        Instruction::Shl { x, y } => format!("v{:x} <<= v{:x}", x, y),
+        Instruction::SneReg { x, y } => format!("if v{:x} == v{:x} then", x, y),
+        Instruction::LdI(nnn) => format!("i := 0x{:03X}", nnn),
+        Instruction::JpV0(nnn) => format!("jump0 0x{:03X}", nnn),
+        Instruction::Rnd { x, nn } => format!("v{:x} := random 0x{:02X}", x, nn),
+        Instruction::Drw { x, y, n } => format!("sprite v{:x} v{:x} {}", x, y, n),
+        Instruction::Skp(x) => format!("if v{:x} -key then", x),
+        Instruction::Sknp(x) => format!("if v{:x} key then", x),
+        Instruction::Plane(x) => format!("plane {}", x),
+        Instruction::Audio => "audio".to_string(),
+        Instruction::LdVxDt(x) => format!("v{:x} := delay", x),
+        Instruction::LdVxK(x) => format!("v{:x} := key", x),
+        Instruction::LdDtVx(x) => format!("delay := v{:x}", x),
+        Instruction::LdStVx(x) => format!("buzzer := v{:x}", x),
+        Instruction::AddI(x) => format!("i += v{:x}", x),
+        Instruction::LdF(x) => format!("i := hex v{:x}", x),
+        Instruction::LdHf(x) => format!("i := bighex v{:x}", x),
+        Instruction::LdB(x) => format!("bcd v{:x}", x),
+        Instruction::Pitch(x) => format!("pitch := v{:x}", x),
+        Instruction::LdIVx(x) => format!("save v{:x}", x),
+        Instruction::LdVxI(x) => format!("load v{:x}", x),
+        Instruction::LdRVx(x) => format!("saveflags v{:x}", x),
+        Instruction::LdVxR(x) => format!("loadflags v{:x}", x),
+        _ => return None,
+    };
+    Some(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(bytes: &[u8], syntax: Syntax) -> Vec<String> {
+        disassemble(bytes, 0x200, syntax).into_iter().map(|line| line.text).collect()
+    }
+
+    #[test]
+    fn classic_mnemonics() {
+        let rom = [0x00, 0xE0, 0x63, 0x1F, 0xD0, 0x15, 0x3A, 0x07, 0x8A, 0xBE, 0xF2, 0x33, 0x00, 0xFF, 0x00, 0xC4];
+        assert_eq!(texts(&rom, Syntax::Classic), [
+            "CLS", "LD V3, 0x1F", "DRW V0, V1, 5", "SE VA, 0x07", "SHL VA, VB", "LD B, V2", "HIGH", "SCD 4",
+        ]);
+    }
+
+    #[test]
+    fn octo_mnemonics() {
+        let rom = [0x00, 0xE0, 0x63, 0x1F, 0xD0, 0x15, 0x3A, 0x07, 0x8A, 0xBE, 0xF2, 0x33, 0x22, 0x40, 0xE1, 0xA1];
+        assert_eq!(texts(&rom, Syntax::Octo), [
+            "clear", "v3 := 0x1F", "sprite v0 v1 5", "if va != 0x07 then", "va <<= vb", "bcd v2", ":call 0x240", "if v1 key then",
+        ]);
+    }
+
+    #[test]
+    fn xo_chip() {
+        let rom = [0xF0, 0x00, 0x12, 0x34, 0xF2, 0x01, 0x51, 0x32, 0xF0, 0x02];
+        assert_eq!(texts(&rom, Syntax::Classic), ["LD I, LONG 0x1234", "PLANE 2", "SAVE V1 - V3", "AUDIO"]);
+        assert_eq!(texts(&rom, Syntax::Octo), ["i := long 0x1234", "plane 2", "save v1 - v3", "audio"]);
+
+        let lines = disassemble(&rom, 0x200, Syntax::Classic);
+        assert_eq!(lines[0].bytes, [0xF0, 0x00, 0x12, 0x34]);
+        assert_eq!(lines[1].address, 0x204);
+    }
+
+    #[test]
+    fn data() {
+        // Unknown words and a trailing odd byte don't stop the listing
+        let rom = [0x5A, 0xB1, 0xFF, 0xFF, 0x00, 0xEE, 0x7F];
+        assert_eq!(texts(&rom, Syntax::Classic), ["DW 0x5AB1", "DW 0xFFFF", "RET", "DB 0x7F"]);
+        assert_eq!(texts(&rom, Syntax::Octo), ["0x5A 0xB1", "0xFF 0xFF", "return", "0x7F"]);
+        assert_eq!(disassemble(&rom, 0x200, Syntax::Classic)[3].address, 0x206);
+    }
+
+    #[test]
+    fn listing() {
+        let line = &disassemble(&[0x12, 0x00], 0x200, Syntax::Classic)[0];
+        assert_eq!(line.to_string(), "0200  1200      JP 0x200");
+    }
+}
@@ -0,0 +1,179 @@
+pub const SCREEN_WIDTH: usize = 64;
+pub const SCREEN_HEIGHT: usize = 32;
+pub const HIRES_SCREEN_WIDTH: usize = 128;
+pub const HIRES_SCREEN_HEIGHT: usize = 64;
+pub const NUM_PLANES: usize = 2;
+pub const NUM_COLORS: usize = 1 << NUM_PLANES;
+
+// Framebuffer that can switch between 64x32 (lores) and 128x64 (hires)
+// Each pixel is a bitmask of the planes it is lit in, so XO-CHIP's two planes give four colors
+#[derive(Debug, Clone)]
+pub struct Display {
+    width: usize,
+    height: usize,
+    pub(crate) buffer: Vec<u8>,
+}
+
+impl Display {
+    pub fn new() -> Self {
+        Self {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            buffer: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.width == HIRES_SCREEN_WIDTH
+    }
+
+    /// Plane index (0 to `NUM_COLORS - 1`) of every pixel in row-major order, `width() * height()` long
+    pub fn pixels(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Plane index of a single pixel, 0 if unlit
+    pub fn plane(&self, x: usize, y: usize) -> u8 {
+        self.buffer[(y * self.width) + x]
+    }
+
+    /// True if the pixel is lit in any plane
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        self.plane(x, y) != 0
+    }
+
+    // Switching resolution also clears the screen
+    pub(crate) fn set_hires(&mut self, hires: bool) {
+        (self.width, self.height) = match hires {
+            true => (HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT),
+            false => (SCREEN_WIDTH, SCREEN_HEIGHT),
+        };
+        self.buffer = vec![0; self.width * self.height];
+    }
+
+    pub(crate) fn clear(&mut self, planes: u8) {
+        for pixel in self.buffer.iter_mut() {
+            *pixel &= !planes;
+        }
+    }
+
+    // Move every row down by n pixels, filling in blank rows at the top
+    pub(crate) fn scroll_down(&mut self, n: usize, planes: u8) {
+        self.shift(0, n as isize, planes);
+    }
+
+    // Move every row up by n pixels, filling in blank rows at the bottom
+    pub(crate) fn scroll_up(&mut self, n: usize, planes: u8) {
+        self.shift(0, -(n as isize), planes);
+    }
+
+    // Move every column right by n pixels, filling in blank columns on the left
+    pub(crate) fn scroll_right(&mut self, n: usize, planes: u8) {
+        self.shift(n as isize, 0, planes);
+    }
+
+    // Move every column left by n pixels, filling in blank columns on the right
+    pub(crate) fn scroll_left(&mut self, n: usize, planes: u8) {
+        self.shift(-(n as isize), 0, planes);
+    }
+
+    // Shift the selected planes by (dx, dy), leaving the other planes where they are
+    fn shift(&mut self, dx: isize, dy: isize, planes: u8) {
+        let source = self.buffer.clone();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let source_x = x as isize - dx;
+                let source_y = y as isize - dy;
+                let shifted = if (0..self.width as isize).contains(&source_x) && (0..self.height as isize).contains(&source_y) {
+                    source[(source_y as usize * self.width) + source_x as usize]
+                } else {
+                    0
+                };
+
+                let index = (y * self.width) + x;
+                self.buffer[index] = (self.buffer[index] & !planes) | (shifted & planes);
+            }
+        }
+    }
+
+    // XOR a pixel onto a single plane, returning true if a lit pixel was erased
+    pub(crate) fn flip(&mut self, x: usize, y: usize, plane: u8) -> bool {
+        let index = (y * self.width) + x;
+        let collide = self.buffer[index] & plane != 0;
+        self.buffer[index] ^= plane;
+        collide
+    }
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn switch_resolution() {
+        let mut display = Display::new();
+        assert!(!display.flip(3, 2, 1));
+        assert!(display.pixel(3, 2));
+        display.set_hires(true);
+        assert_eq!((display.width(), display.height()), (HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT));
+        assert_eq!(display.pixels().len(), HIRES_SCREEN_WIDTH * HIRES_SCREEN_HEIGHT);
+        assert!(display.pixels().iter().all(|p| *p == 0));
+        display.set_hires(false);
+        assert!(!display.is_hires());
+        assert_eq!(display.pixels().len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+    }
+
+    #[test]
+    fn scroll() {
+        let mut display = Display::new();
+        display.flip(1, 1, 1);
+        display.scroll_down(3, 1);
+        display.scroll_up(1, 1);
+        assert!(display.pixel(1, 3));
+        assert!(!display.pixel(1, 1));
+        display.scroll_right(4, 1);
+        assert!(display.pixel(5, 3));
+        display.scroll_left(5, 1);
+        assert!(display.pixel(0, 3));
+        assert_eq!(display.pixels().iter().filter(|p| **p != 0).count(), 1);
+        display.scroll_left(1, 1);
+        assert!(display.pixels().iter().all(|p| *p == 0));
+    }
+
+    #[test]
+    fn scroll_selected_planes() {
+        let mut display = Display::new();
+        display.flip(0, 0, 1);
+        display.flip(0, 0, 2);
+        assert_eq!(display.plane(0, 0), 3);
+        display.scroll_down(1, 2);
+        assert_eq!(display.plane(0, 0), 1);
+        assert_eq!(display.plane(0, 1), 2);
+        display.clear(1);
+        assert_eq!(display.plane(0, 0), 0);
+        assert_eq!(display.plane(0, 1), 2);
+    }
+
+    #[test]
+    fn flip_collision() {
+        let mut display = Display::new();
+        assert!(!display.flip(0, 0, 1));
+        assert!(!display.flip(0, 0, 2));
+        assert!(display.flip(0, 0, 1));
+        assert_eq!(display.plane(0, 0), 2);
+    }
+}
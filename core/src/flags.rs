@@ -0,0 +1,11 @@
+use std::io;
+
+// Persistence for the RPL user flags written by FX75 and read by FX85.
+// Frontends implement this to keep the flags (usually high scores) between runs of a ROM.
+pub trait FlagStorage {
+    /// Return the saved flags, or None if nothing has been saved yet
+    fn load(&mut self) -> io::Result<Option<Vec<u8>>>;
+
+    /// Save the current flags, called every time FX75 runs
+    fn save(&mut self, flags: &[u8]) -> io::Result<()>;
+}
@@ -0,0 +1,279 @@
+use crate::core_error::CoreError;
+use crate::slice_u16;
+
+// Every instruction of CHIP-8, SUPER-CHIP and XO-CHIP. Decoding doesn't depend on quirks,
+// so the CPU decides which of these it runs. x and y are register indices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Instruction {
+    /// 0NNN, machine code routine. 0000 is a no-op
+    Sys(u16),
+    /// 00CN
+    ScrollDown(u8),
+    /// 00DN
+    ScrollUp(u8),
+    /// 00E0
+    Cls,
+    /// 00EE
+    Ret,
+    /// 00FB
+    ScrollRight,
+    /// 00FC
+    ScrollLeft,
+    /// 00FD
+    Exit,
+    /// 00FE
+    Lores,
+    /// 00FF
+    Hires,
+    /// 1NNN
+    Jp(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XNN
+    SeByte { x: u8, nn: u8 },
+    /// 4XNN
+    SneByte { x: u8, nn: u8 },
+    /// 5XY0
+    SeReg { x: u8, y: u8 },
+    /// 5XY2
+    SaveRange { x: u8, y: u8 },
+    /// 5XY3
+    LoadRange { x: u8, y: u8 },
+    /// 6XNN
+    LdByte { x: u8, nn: u8 },
+    /// 7XNN
+    AddByte { x: u8, nn: u8 },
+    /// 8XY0
+    LdReg { x: u8, y: u8 },
+    /// 8XY1
+    Or { x: u8, y: u8 },
+    /// 8XY2
+    And { x: u8, y: u8 },
+    /// 8XY3
+    Xor { x: u8, y: u8 },
+    /// 8XY4
+    AddReg { x: u8, y: u8 },
+    /// 8XY5
+    Sub { x: u8, y: u8 },
+    /// 8XY6
+    Shr { x: u8, y: u8 },
+    /// 8XY7
+    Subn { x: u8, y: u8 },
+    /// 8XYE
+    Shl { x: u8, y: u8 },
+    /// 9XY0
+    SneReg { x: u8, y: u8 },
+    /// ANNN
+    LdI(u16),
+    /// BNNN
+    JpV0(u16),
+    /// CXNN
+    Rnd { x: u8, nn: u8 },
+    /// DXYN
+    Drw { x: u8, y: u8, n: u8 },
+    /// EX9E
+    Skp(u8),
+    /// EXA1
+    Sknp(u8),
+    /// F000, followed by the 16-bit address in the next word
+    LongI,
+    /// FN01
+    Plane(u8),
+    /// F002
+    Audio,
+    /// FX07
+    LdVxDt(u8),
+    /// FX0A
+    LdVxK(u8),
+    /// FX15
+    LdDtVx(u8),
+    /// FX18
+    LdStVx(u8),
+    /// FX1E
+    AddI(u8),
+    /// FX29
+    LdF(u8),
+    /// FX30
+    LdHf(u8),
+    /// FX33
+    LdB(u8),
+    /// FX3A
+    Pitch(u8),
+    /// FX55
+    LdIVx(u8),
+    /// FX65
+    LdVxI(u8),
+    /// FX75
+    LdRVx(u8),
+    /// FX85
+    LdVxR(u8),
+}
+
+impl Instruction {
+    /// Returns OpcodeError for words that aren't an instruction in any of the supported variants
+    pub fn decode(op_code: u16) -> Result<Instruction, CoreError> {
+        let (_, x, y, n) = slice_u16(op_code);
+        let (x, y, n) = (x as u8, y as u8, n as u8);
+        let nnn = op_code & 0x0FFF;
+        let nn = (op_code & 0x00FF) as u8;
+
+        let instruction = match slice_u16(op_code) {
+            (0x0, 0x0, 0xC, _) => Instruction::ScrollDown(n),
+            (0x0, 0x0, 0xD, _) => Instruction::ScrollUp(n),
+            (0x0, 0x0, 0xE, 0x0) => Instruction::Cls,
+            (0x0, 0x0, 0xE, 0xE) => Instruction::Ret,
+            (0x0, 0x0, 0xF, 0xB) => Instruction::ScrollRight,
+            (0x0, 0x0, 0xF, 0xC) => Instruction::ScrollLeft,
+            (0x0, 0x0, 0xF, 0xD) => Instruction::Exit,
+            (0x0, 0x0, 0xF, 0xE) => Instruction::Lores,
+            (0x0, 0x0, 0xF, 0xF) => Instruction::Hires,
+            (0x0, _, _, _) => Instruction::Sys(nnn),
+            (0x1, _, _, _) => Instruction::Jp(nnn),
+            (0x2, _, _, _) => Instruction::Call(nnn),
+            (0x3, _, _, _) => Instruction::SeByte { x, nn },
+            (0x4, _, _, _) => Instruction::SneByte { x, nn },
+            (0x5, _, _, 0x0) => Instruction::SeReg { x, y },
+            (0x5, _, _, 0x2) => Instruction::SaveRange { x, y },
+            (0x5, _, _, 0x3) => Instruction::LoadRange { x, y },
+            (0x6, _, _, _) => Instruction::LdByte { x, nn },
+            (0x7, _, _, _) => Instruction::AddByte { x, nn },
+            (0x8, _, _, 0x0) => Instruction::LdReg { x, y },
+            (0x8, _, _, 0x1) => Instruction::Or { x, y },
+            (0x8, _, _, 0x2) => Instruction::And { x, y },
+            (0x8, _, _, 0x3) => Instruction::Xor { x, y },
+            (0x8, _, _, 0x4) => Instruction::AddReg { x, y },
+            (0x8, _, _, 0x5) => Instruction::Sub { x, y },
+            (0x8, _, _, 0x6) => Instruction::Shr { x, y },
+            (0x8, _, _, 0x7) => Instruction::Subn { x, y },
+            (0x8, _, _, 0xE) => Instruction::Shl { x, y },
+            (0x9, _, _, 0x0) => Instruction::SneReg { x, y },
+            (0xA, _, _, _) => Instruction::LdI(nnn),
+            (0xB, _, _, _) => Instruction::JpV0(nnn),
+            (0xC, _, _, _) => Instruction::Rnd { x, nn },
+            (0xD, _, _, _) => Instruction::Drw { x, y, n },
+            (0xE, _, 0x9, 0xE) => Instruction::Skp(x),
+            (0xE, _, 0xA, 0x1) => Instruction::Sknp(x),
+            (0xF, 0x0, 0x0, 0x0) => Instruction::LongI,
+            (0xF, _, 0x0, 0x1) => Instruction::Plane(x),
+            (0xF, 0x0, 0x0, 0x2) => Instruction::Audio,
+            (0xF, _, 0x0, 0x7) => Instruction::LdVxDt(x),
+            (0xF, _, 0x0, 0xA) => Instruction::LdVxK(x),
+            (0xF, _, 0x1, 0x5) => Instruction::LdDtVx(x),
+            (0xF, _, 0x1, 0x8) => Instruction::LdStVx(x),
+            (0xF, _, 0x1, 0xE) => Instruction::AddI(x),
+            (0xF, _, 0x2, 0x9) => Instruction::LdF(x),
+            (0xF, _, 0x3, 0x0) => Instruction::LdHf(x),
+            (0xF, _, 0x3, 0x3) => Instruction::LdB(x),
+            (0xF, _, 0x3, 0xA) => Instruction::Pitch(x),
+            (0xF, _, 0x5, 0x5) => Instruction::LdIVx(x),
+            (0xF, _, 0x6, 0x5) => Instruction::LdVxI(x),
+            (0xF, _, 0x7, 0x5) => Instruction::LdRVx(x),
+            (0xF, _, 0x8, 0x5) => Instruction::LdVxR(x),
+            _ => return Err(CoreError::OpcodeError { opcode: op_code }),
+        };
+        Ok(instruction)
+    }
+
+    /// The opcode for this instruction. Operands are masked to their field width
+    pub fn encode(self) -> u16 {
+        fn xnn(prefix: u16, x: u8, nn: u8) -> u16 {
+            prefix << 12 | (x as u16 & 0xF) << 8 | nn as u16
+        }
+        fn xyn(prefix: u16, x: u8, y: u8, n: u8) -> u16 {
+            prefix << 12 | (x as u16 & 0xF) << 8 | (y as u16 & 0xF) << 4 | (n as u16 & 0xF)
+        }
+        fn fx(x: u8, low: u8) -> u16 {
+            xnn(0xF, x, low)
+        }
+
+        match self {
+            Instruction::Sys(nnn) => nnn & 0x0FFF,
+            Instruction::ScrollDown(n) => 0x00C0 | (n as u16 & 0xF),
+            Instruction::ScrollUp(n) => 0x00D0 | (n as u16 & 0xF),
+            Instruction::Cls => 0x00E0,
+            Instruction::Ret => 0x00EE,
+            Instruction::ScrollRight => 0x00FB,
+            Instruction::ScrollLeft => 0x00FC,
+            Instruction::Exit => 0x00FD,
+            Instruction::Lores => 0x00FE,
+            Instruction::Hires => 0x00FF,
+            Instruction::Jp(nnn) => 0x1000 | (nnn & 0x0FFF),
+            Instruction::Call(nnn) => 0x2000 | (nnn & 0x0FFF),
+            Instruction::SeByte { x, nn } => xnn(0x3, x, nn),
+            Instruction::SneByte { x, nn } => xnn(0x4, x, nn),
+            Instruction::SeReg { x, y } => xyn(0x5, x, y, 0x0),
+            Instruction::SaveRange { x, y } => xyn(0x5, x, y, 0x2),
+            Instruction::LoadRange { x, y } => xyn(0x5, x, y, 0x3),
+            Instruction::LdByte { x, nn } => xnn(0x6, x, nn),
+            Instruction::AddByte { x, nn } => xnn(0x7, x, nn),
+            Instruction::LdReg { x, y } => xyn(0x8, x, y, 0x0),
+            Instruction::Or { x, y } => xyn(0x8, x, y, 0x1),
+            Instruction::And { x, y } => xyn(0x8, x, y, 0x2),
+            Instruction::Xor { x, y } => xyn(0x8, x, y, 0x3),
+            Instruction::AddReg { x, y } => xyn(0x8, x, y, 0x4),
+            Instruction::Sub { x, y } => xyn(0x8, x, y, 0x5),
+            Instruction::Shr { x, y } => xyn(0x8, x, y, 0x6),
+            Instruction::Subn { x, y } => xyn(0x8, x, y, 0x7),
+            Instruction::Shl { x, y } => xyn(0x8, x, y, 0xE),
+            Instruction::SneReg { x, y } => xyn(0x9, x, y, 0x0),
+            Instruction::LdI(nnn) => 0xA000 | (nnn & 0x0FFF),
+            Instruction::JpV0(nnn) => 0xB000 | (nnn & 0x0FFF),
+            Instruction::Rnd { x, nn } => xnn(0xC, x, nn),
+            Instruction::Drw { x, y, n } => xyn(0xD, x, y, n),
+            Instruction::Skp(x) => xnn(0xE, x, 0x9E),
+            Instruction::Sknp(x) => xnn(0xE, x, 0xA1),
+            Instruction::LongI => 0xF000,
+            Instruction::Plane(n) => fx(n, 0x01),
+            Instruction::Audio => 0xF002,
+            Instruction::LdVxDt(x) => fx(x, 0x07),
+            Instruction::LdVxK(x) => fx(x, 0x0A),
+            Instruction::LdDtVx(x) => fx(x, 0x15),
+            Instruction::LdStVx(x) => fx(x, 0x18),
+            Instruction::AddI(x) => fx(x, 0x1E),
+            Instruction::LdF(x) => fx(x, 0x29),
+            Instruction::LdHf(x) => fx(x, 0x30),
+            Instruction::LdB(x) => fx(x, 0x33),
+            Instruction::Pitch(x) => fx(x, 0x3A),
+            Instruction::LdIVx(x) => fx(x, 0x55),
+            Instruction::LdVxI(x) => fx(x, 0x65),
+            Instruction::LdRVx(x) => fx(x, 0x75),
+            Instruction::LdVxR(x) => fx(x, 0x85),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut decoded = 0;
+        for op_code in 0..=u16::MAX {
+            if let Ok(instruction) = Instruction::decode(op_code) {
+                assert_eq!(instruction.encode(), op_code, "{:?} from {:04X}", instruction, op_code);
+                assert_eq!(Instruction::decode(instruction.encode()).unwrap(), instruction);
+                decoded += 1;
+            }
+        }
+        // Every word of the 11 prefixes with no fixed low bits, then 5XYN, 8XYN and 9XYN by low nibble,
+        // EX by low byte, and FX by low byte plus F000, F002 and the 16 FN01 planes
+        assert_eq!(decoded, 11 * 4096 + (3 + 9 + 1) * 256 + 2 * 16 + (13 * 16 + 2 + 16));
+    }
+
+    #[test]
+    fn operands() {
+        assert_eq!(Instruction::decode(0xD125).unwrap(), Instruction::Drw { x: 1, y: 2, n: 5 });
+        assert_eq!(Instruction::decode(0x3A07).unwrap(), Instruction::SeByte { x: 0xA, nn: 0x07 });
+        assert_eq!(Instruction::decode(0x0000).unwrap(), Instruction::Sys(0));
+        assert_eq!(Instruction::decode(0xF301).unwrap(), Instruction::Plane(3));
+        assert_eq!(Instruction::Jp(0x1234).encode(), 0x1234);
+    }
+
+    #[test]
+    fn unknown() {
+        for op_code in [0x5AB1, 0x800F, 0x9001, 0xE000, 0xF100, 0xF102, 0xFFFF] {
+            assert!(matches!(Instruction::decode(op_code), Err(CoreError::OpcodeError { opcode }) if opcode == op_code));
+        }
+    }
+}
@@ -1,103 +1,264 @@
 mod fonts;
 mod core_error;
+mod audio;
+#[cfg(feature = "cli")]
+pub mod cli;
+mod debugger;
+mod disasm;
+mod display;
+mod flags;
+mod instruction;
+mod movie;
+mod quirks;
+mod random;
+mod rewind;
+mod scheduler;
+mod state;
+#[cfg(test)]
+mod test_util;
+mod timing;
 
 use std::error;
 
-use rand;
-
-use crate::core_error::CoreError;
-use crate::fonts::FONT_SET_1;
-
-pub const SCREEN_WIDTH: usize = 64;
-pub const SCREEN_HEIGHT: usize = 32;
-const SCREEN_BUFF_SIZE: usize = SCREEN_WIDTH * SCREEN_HEIGHT;
+use crate::fonts::{FONT_SET_1, FONT_SET_LARGE};
+
+pub use crate::core_error::CoreError;
+pub use crate::audio::{AudioSource, SquareWave, PatternPlayer, pattern_rate, DEFAULT_PITCH_HZ, DEFAULT_VOLUME};
+pub use crate::debugger::Breakpoint;
+pub use crate::disasm::{disassemble, DisassemblyLine, Syntax};
+pub use crate::display::{Display, SCREEN_WIDTH, SCREEN_HEIGHT, HIRES_SCREEN_WIDTH, HIRES_SCREEN_HEIGHT, NUM_PLANES, NUM_COLORS};
+pub use crate::flags::FlagStorage;
+pub use crate::instruction::Instruction;
+pub use crate::movie::{fnv1a, KeyEvent, Movie, MovieEnd, MoviePlayer};
+pub use crate::quirks::Quirks;
+pub use crate::random::{RandomSource, Xorshift, ScriptedRandom};
+pub use crate::rewind::{Rewind, DEFAULT_REWIND_FRAMES, DEFAULT_REWIND_BYTES};
+pub use crate::scheduler::{Clock, InstructionHook, Scheduler, SystemClock, TIMER_HZ};
+pub use crate::timing::{Timing, VIP_CYCLES_PER_FRAME};
 
 const RAM_SIZE: usize = 4096;
-const START_ADDRESS: u16 = 0x200;
+const XO_RAM_SIZE: usize = 65536;
+pub const START_ADDRESS: u16 = 0x200;
 const NUM_REGISTERS: usize = 16;
 const STACK_SIZE: usize = 16;
 const NUM_KEYS: usize = 16;
+const NUM_RPL_FLAGS: usize = 8;
+const XO_NUM_RPL_FLAGS: usize = 16;
+const DEFAULT_PLANES: u8 = 1;
+pub const AUDIO_PATTERN_BYTES: usize = 16;
+// Square wave pattern so plain FX18 still beeps before a ROM loads its own pattern
+const DEFAULT_AUDIO_PATTERN: [u8; AUDIO_PATTERN_BYTES] = [0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00];
+const DEFAULT_PITCH: u8 = 64;
+const LONG_INDEX_OPCODE: u16 = 0xF000;
 const FONT_ADDRESS_OFFSET: u16 = 0;
+const LARGE_FONT_ADDRESS_OFFSET: u16 = FONT_ADDRESS_OFFSET + FONT_SET_1.len() as u16;
+const FONT_BYTES: u16 = 5;
+const LARGE_FONT_BYTES: u16 = 10;
 const SPRITE_WIDTH: usize = 8;
-const SPRITE_BYTES_MAX: usize = 15;
+const LARGE_SPRITE_WIDTH: usize = 16;
+const LARGE_SPRITE_HEIGHT: usize = 16;
 
 pub struct CPU {
     program_counter: u16,
-    ram: [u8; RAM_SIZE],
+    ram: Vec<u8>,
     index_register: u16,
     v_register: [u8; NUM_REGISTERS],
     stack: Vec<u16>,
     delay_timer: u8,
     sound_timer: u8,
-    display_buffer: [bool; SCREEN_BUFF_SIZE],
+    display: Display,
     pub display_update_flag: bool,
     key_states: [bool; NUM_KEYS],
     pressed_key: u8,
     wait_for_press: bool,
+    wait_for_vblank: bool,
+    selected_planes: u8,
+    audio_pattern: [u8; AUDIO_PATTERN_BYTES],
+    pitch: u8,
+    rpl_flags: [u8; XO_NUM_RPL_FLAGS],
+    flag_storage: Option<Box<dyn FlagStorage>>,
+    rng: Box<dyn RandomSource>,
+    quirks: Quirks,
+    timing: Timing,
 }
 
 impl CPU {
     pub fn new() -> Self {
+        Self::with_quirks(Quirks::default())
+    }
+
+    pub fn with_quirks(quirks: Quirks) -> Self {
         let mut new_cpu = Self {
             program_counter: START_ADDRESS,
-            ram: [0; RAM_SIZE],
+            ram: vec![0; memory_size(&quirks)],
             index_register: 0,
             v_register: [0; NUM_REGISTERS],
             stack: Vec::with_capacity(STACK_SIZE),
             delay_timer: 0,
             sound_timer: 0,
-            display_buffer: [false; SCREEN_BUFF_SIZE],
+            display: Display::new(),
             display_update_flag: false,
             key_states: [false; NUM_KEYS],
             pressed_key: 0,
             wait_for_press: true,
+            wait_for_vblank: false,
+            selected_planes: DEFAULT_PLANES,
+            audio_pattern: DEFAULT_AUDIO_PATTERN,
+            pitch: DEFAULT_PITCH,
+            rpl_flags: [0; XO_NUM_RPL_FLAGS],
+            flag_storage: None,
+            rng: Box::new(Xorshift::new(rand::random())),
+            quirks,
+            timing: Timing::default(),
         };
 
         new_cpu.load_font(&FONT_ADDRESS_OFFSET, &FONT_SET_1);
+        new_cpu.load_font(&LARGE_FONT_ADDRESS_OFFSET, &FONT_SET_LARGE);
         new_cpu
     }
 
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    // Changing quirks also resizes RAM if the address space changes
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+        self.ram.resize(memory_size(&quirks), 0);
+    }
+
+    // Attach persistent storage for the RPL user flags, restoring any previously saved flags
+    pub fn set_flag_storage(&mut self, mut storage: Box<dyn FlagStorage>) -> Result<(), CoreError> {
+        if let Some(saved) = storage.load().map_err(|source| CoreError::FlagStorageError { source })? {
+            let count = saved.len().min(XO_NUM_RPL_FLAGS);
+            self.rpl_flags[..count].copy_from_slice(&saved[..count]);
+        }
+        self.flag_storage = Some(storage);
+        Ok(())
+    }
+
+    fn num_rpl_flags(&self) -> usize {
+        if self.quirks.xo_chip_opcodes { XO_NUM_RPL_FLAGS } else { NUM_RPL_FLAGS }
+    }
+
+    // Replace the source of CXNN's random numbers, e.g. with a seeded one for reproducible runs
+    pub fn set_random_source(&mut self, rng: Box<dyn RandomSource>) {
+        self.rng = rng;
+    }
+
+    pub fn seed_random(&mut self, seed: u64) {
+        self.set_random_source(Box::new(Xorshift::new(seed)));
+    }
+
+    pub fn v_register(&self, index: usize) -> u8 {
+        self.v_register[index]
+    }
+
+    pub fn v_registers(&self) -> &[u8; NUM_REGISTERS] {
+        &self.v_register
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    pub fn index_register(&self) -> u16 {
+        self.index_register
+    }
+
+    // Return addresses, innermost call last
+    pub fn stack(&self) -> &[u16] {
+        &self.stack
+    }
+
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    // All of RAM, including the fonts below START_ADDRESS
+    pub fn memory(&self) -> &[u8] {
+        &self.ram
+    }
+
+    // Current state of the random source, which is also the seed that carries on from here
+    pub fn random_state(&self) -> u64 {
+        self.rng.state()
+    }
+
+    // RPL user flags as last written by FX75
+    pub fn rpl_flags(&self) -> &[u8] {
+        &self.rpl_flags[..self.num_rpl_flags()]
+    }
+
+    pub fn timing(&self) -> Timing {
+        self.timing
+    }
+
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.timing = timing;
+    }
+
     pub fn reset(&mut self) {
         self.program_counter = START_ADDRESS;
-        self.ram = [0; RAM_SIZE];
+        self.ram = vec![0; memory_size(&self.quirks)];
         self.index_register = 0;
         self.v_register = [0; NUM_REGISTERS];
         self.stack = Vec::with_capacity(STACK_SIZE);
         self.delay_timer = 0;
         self.sound_timer = 0;
-        self.display_buffer = [false; SCREEN_BUFF_SIZE];
+        self.display = Display::new();
         self.display_update_flag = false;
         self.key_states = [false; NUM_KEYS];
         self.pressed_key = 0;
         self.wait_for_press = true;
+        self.wait_for_vblank = false;
+        self.selected_planes = DEFAULT_PLANES;
+        self.audio_pattern = DEFAULT_AUDIO_PATTERN;
+        self.pitch = DEFAULT_PITCH;
 
         // self.ram[(FONT_ADDRESS_OFFSET as usize)..(FONT_ADDRESS_OFFSET as usize) + FONT_SET_1.len()].copy_from_slice(&FONT_SET_1);
         self.load_font(&FONT_ADDRESS_OFFSET, &FONT_SET_1);
+        self.load_font(&LARGE_FONT_ADDRESS_OFFSET, &FONT_SET_LARGE);
     }
 
-    fn load_font(&mut self, offset: &u16, font: &[u8; 80]) {
+    fn load_font(&mut self, offset: &u16, font: &[u8]) {
         self.ram[(*offset as usize)..(*offset as usize + font.len())].copy_from_slice(font);
     }
 
-    pub fn load_rom_from_buffer(&mut self, rom_buffer: &Vec<u8>) -> Result<(), Box<dyn error::Error>> {
+    pub fn load_rom_from_buffer(&mut self, rom_buffer: &[u8]) -> Result<(), Box<dyn error::Error>> {
         // Load ROM contents into RAM, starting at 0x200
         // let mut rom_file = File::open(path)?;
         // let mut rom_buffer = Vec::new();
         // rom_file.read_to_end(&mut rom_buffer)?;
 
-        if rom_buffer.len() > 0xE00 {
+        if rom_buffer.len() > self.ram.len() - START_ADDRESS as usize {
             return Err(CoreError::RomSizeError.into());
         }
 
         let start = START_ADDRESS as usize;
         let end = (START_ADDRESS as usize) + rom_buffer.len();
-        self.ram[start..end].copy_from_slice(&rom_buffer);
+        self.ram[start..end].copy_from_slice(rom_buffer);
 
         Ok(())
     }
 
-    pub fn get_display(&self) -> &[bool] {
-        &self.display_buffer
+    // Write straight into memory, e.g. to set the options some test ROMs read from below 0x200
+    pub fn write_memory(&mut self, address: u16, bytes: &[u8]) -> Result<(), CoreError> {
+        let start = address as usize;
+        let end = start + bytes.len();
+        if end > self.ram.len() {
+            return Err(CoreError::MemoryError { address: end - 1 });
+        }
+        self.ram[start..end].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    pub fn get_display(&self) -> &Display {
+        &self.display
     }
 
     pub fn keypress(&mut self, index: usize, pressed: bool) -> Result<(), CoreError> {
@@ -109,54 +270,109 @@ impl CPU {
     }
 
     pub fn tick_timers(&mut self) {
+        self.wait_for_vblank = false;
+
         if self.delay_timer > 0 {
             self.delay_timer -= 1;
         }
 
         if self.sound_timer > 0 {
-            if self.sound_timer == 1 {
-                // Tone not implemented
-            }
-
             self.sound_timer -= 1;
         }
     }
 
-    pub fn cycle(&mut self) -> Result<(), CoreError> {
+    // The buzzer sounds for as long as the sound timer is non-zero
+    pub fn buzzer_active(&self) -> bool {
+        self.sound_timer > 0
+    }
+
+    // XO-CHIP audio pattern loaded by F002
+    pub fn audio_pattern(&self) -> &[u8; AUDIO_PATTERN_BYTES] {
+        &self.audio_pattern
+    }
+
+    // Stalled after a draw with the display wait quirk, so the next cycle won't run an instruction
+    pub fn waiting_for_vblank(&self) -> bool {
+        self.wait_for_vblank
+    }
+
+    // XO-CHIP pattern playback pitch set by FX3A, see pattern_rate()
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    // Run one instruction, returning how many cycles it took under the selected timing model.
+    // Nothing runs and no cycles pass while a draw waits for the next timer tick with the display wait quirk
+    pub fn cycle(&mut self) -> Result<u32, CoreError> {
+        if self.wait_for_vblank {
+            return Ok(0);
+        }
+
         let op_code = self.fetch()?;
+        let next_pc = self.program_counter;
+        let sprite_x = self.v_register[((op_code & 0x0F00) >> 8) as usize];
+
         self.execute(op_code)?;
-        Ok(())
+
+        Ok(match self.timing {
+            Timing::Instruction => 1,
+            Timing::Vip => {
+                // Only skips look at this, and a taken one moves past the next instruction however long it is
+                let skipped = self.program_counter != next_pc;
+                timing::vip_cycles(op_code, skipped, sprite_x)
+            },
+        })
     }
 
     fn fetch(&mut self) -> Result<u16, CoreError> {
         // Program would panic if program_counter is higher than ram.len()
         // Instead, check the bounds and return ProgramCounterError in case of problem
-        if ((self.program_counter + 1) as usize) >= RAM_SIZE {
+        if (self.program_counter as usize + 1) >= self.ram.len() {
              return Err(CoreError::ProgramCounterError { index: self.program_counter });
         }
 
         let upper_byte = self.ram[self.program_counter as usize];
-        let lower_byte = self.ram[(self.program_counter + 1) as usize];
+        let lower_byte = self.ram[self.program_counter as usize + 1];
 
         let opcode: u16 = (upper_byte as u16) << 8 | lower_byte as u16;
-        self.program_counter += 2;
-        
+        self.program_counter = self.program_counter.wrapping_add(2);
+
         Ok(opcode)
     }
 
+    // Skip the next instruction. XO-CHIP F000 NNNN is 4 bytes long, so it needs to be skipped as a whole
+    fn skip_instruction(&mut self) {
+        let pc = self.program_counter as usize;
+        let next_is_long = self.quirks.xo_chip_opcodes
+            && pc + 1 < self.ram.len()
+            && ((self.ram[pc] as u16) << 8 | self.ram[pc + 1] as u16) == LONG_INDEX_OPCODE;
+
+        self.program_counter = self.program_counter.wrapping_add(if next_is_long { 4 } else { 2 });
+    }
+
     fn execute(&mut self, op_code: u16) -> Result<(), CoreError> {
-        let (nib4, nib3, nib2, nib1) = slice_u16(op_code);
         let  mut execute_result = Ok(());
 
-        match (nib4, nib3, nib2, nib1) {
-            (0, 0, 0, 0) => (), // NOP
+        match Instruction::decode(op_code)? {
+            Instruction::Sys(0) => (), // NOP
+
+            Instruction::ScrollDown(n) if self.quirks.schip_opcodes => { // Scroll display down n pixels
+                let distance = self.scroll_distance(n as usize);
+                self.display.scroll_down(distance, self.selected_planes);
+                self.display_update_flag = true;
+            },
+
+            Instruction::ScrollUp(n) if self.quirks.xo_chip_opcodes => { // Scroll display up n pixels
+                self.display.scroll_up(n as usize, self.selected_planes);
+                self.display_update_flag = true;
+            },
 
-            (0, 0, 0xE, 0) => { // Clear screen
-                self.display_buffer = [false; SCREEN_BUFF_SIZE];
+            Instruction::Cls => { // Clear screen
+                self.display.clear(self.selected_planes);
                 self.display_update_flag = true;
             },
 
-            (0, 0, 0xE, 0xE) => { // Return (exit subroutine)
+            Instruction::Ret => { // Return (exit subroutine)
                 let stack_pop = self.stack.pop();
                 match stack_pop {
                     Some(addr) => self.program_counter = addr,
@@ -164,47 +380,98 @@ impl CPU {
                 }
             },
 
-            (1, _, _, _) => self.program_counter = op_code & 0x0FFF, // Jump
+            Instruction::ScrollRight if self.quirks.schip_opcodes => { // Scroll display right 4 pixels
+                let distance = self.scroll_distance(4);
+                self.display.scroll_right(distance, self.selected_planes);
+                self.display_update_flag = true;
+            },
+
+            Instruction::ScrollLeft if self.quirks.schip_opcodes => { // Scroll display left 4 pixels
+                let distance = self.scroll_distance(4);
+                self.display.scroll_left(distance, self.selected_planes);
+                self.display_update_flag = true;
+            },
+
+            Instruction::Lores if self.quirks.schip_opcodes => { // Switch to lores (64x32) mode
+                self.display.set_hires(false);
+                self.display_update_flag = true;
+            },
 
-            (2, _, _, _) => { // Call subroutine
+            Instruction::Hires if self.quirks.schip_opcodes => { // Switch to hires (128x64) mode
+                self.display.set_hires(true);
+                self.display_update_flag = true;
+            },
+
+            Instruction::Jp(nnn) => self.program_counter = nnn, // Jump
+
+            Instruction::Call(nnn) => { // Call subroutine
+                if self.stack.len() >= STACK_SIZE {
+                    return Err(CoreError::StackFullError);
+                }
                 self.stack.push(self.program_counter);
-                self.program_counter = op_code & 0x0FFF;
+                self.program_counter = nnn;
             },
 
-            (3, x, _, _) => { // Skip if vx == NN
-                if self.v_register[x as usize] == (op_code & 0x00FF) as u8 {self.program_counter += 2};
+            Instruction::SeByte { x, nn } => { // Skip if vx == NN
+                if self.v_register[x as usize] == nn {self.skip_instruction()};
             },
 
-            (4, x, _, _) => { // Skip if vx != NN
-                if self.v_register[x as usize] != (op_code & 0x00FF) as u8 {self.program_counter += 2};
+            Instruction::SneByte { x, nn } => { // Skip if vx != NN
+                if self.v_register[x as usize] != nn {self.skip_instruction()};
             },
 
-            (5, x, y, 0) => { // Skip if vx == vy
-                if self.v_register[x as usize] == self.v_register[y as usize] {self.program_counter += 2};
+            Instruction::SeReg { x, y } => { // Skip if vx == vy
+                if self.v_register[x as usize] == self.v_register[y as usize] {self.skip_instruction()};
             },
 
-            (6, x, _, _) => self.v_register[x as usize] = (op_code & 0x00FF) as u8, // Store NN in vx
+            Instruction::SaveRange { x, y } if self.quirks.xo_chip_opcodes => { // Copy v[x..=y] to ram starting at i, in reverse if x > y. i is unchanged
+                for (offset, register) in register_range(x, y).enumerate() {
+                    let address = self.index_register as usize + offset;
+                    if address >= self.ram.len() {
+                        return Err(CoreError::IndexRegisterError { index: address as u16 });
+                    }
+                    self.ram[address] = self.v_register[register];
+                }
+            },
 
-            (7, x, _, _) => self.v_register[x as usize] = self.v_register[x as usize].wrapping_add((op_code & 0x00FF) as u8), // Add NN to vx
+            Instruction::LoadRange { x, y } if self.quirks.xo_chip_opcodes => { // Copy ram starting at i to v[x..=y], in reverse if x > y. i is unchanged
+                for (offset, register) in register_range(x, y).enumerate() {
+                    let address = self.index_register as usize + offset;
+                    if address >= self.ram.len() {
+                        return Err(CoreError::IndexRegisterError { index: address as u16 });
+                    }
+                    self.v_register[register] = self.ram[address];
+                }
+            },
 
-            (8, x, y, 0) => self.v_register[x as usize] = self.v_register[y as usize], // Store vy in vx
+            Instruction::LdByte { x, nn } => self.v_register[x as usize] = nn, // Store NN in vx
 
-            (8, x, y, 1) => { // // Store vx OR vy in vx
-                self.v_register[x as usize] = self.v_register[x as usize] | self.v_register[y as usize];
-                self.v_register[0xf] = 0;
+            Instruction::AddByte { x, nn } => self.v_register[x as usize] = self.v_register[x as usize].wrapping_add(nn), // Add NN to vx
+
+            Instruction::LdReg { x, y } => self.v_register[x as usize] = self.v_register[y as usize], // Store vy in vx
+
+            Instruction::Or { x, y } => { // Store vx OR vy in vx, reset vf if quirk is set
+                self.v_register[x as usize] |= self.v_register[y as usize];
+                if self.quirks.vf_reset {
+                    self.v_register[0xf] = 0;
+                }
             },
 
-            (8, x, y, 2) => { // Store vx AND vy in vx
-                self.v_register[x as usize] = self.v_register[x as usize] & self.v_register[y as usize];
-                self.v_register[0xf] = 0;
+            Instruction::And { x, y } => { // Store vx AND vy in vx, reset vf if quirk is set
+                self.v_register[x as usize] &= self.v_register[y as usize];
+                if self.quirks.vf_reset {
+                    self.v_register[0xf] = 0;
+                }
             },
 
-            (8, x, y, 3) => { // Store vx XOR vy in vx
-                self.v_register[x as usize] = self.v_register[x as usize] ^ self.v_register[y as usize];
-                self.v_register[0xf] = 0;
+            Instruction::Xor { x, y } => { // Store vx XOR vy in vx, reset vf if quirk is set
+                self.v_register[x as usize] ^= self.v_register[y as usize];
+                if self.quirks.vf_reset {
+                    self.v_register[0xf] = 0;
+                }
             },
 
-            (8, x, y, 4) => { // Store vx + vy in vx, set/unset carry flag vf
+            Instruction::AddReg { x, y } => { // Store vx + vy in vx, set/unset carry flag vf
                 let (sum, carry) = self.v_register[x as usize].overflowing_add(self.v_register[y as usize]);
                 self.v_register[x as usize] = sum;
                 self.v_register[0xf] = match carry {
@@ -213,7 +480,7 @@ impl CPU {
                 };
             },
 
-            (8, x, y, 5) => { // Store vx - vy in vx, set/unset carry flag vf
+            Instruction::Sub { x, y } => { // Store vx - vy in vx, set/unset carry flag vf
                 let (sum, carry) = self.v_register[x as usize].overflowing_sub(self.v_register[y as usize]);
                 self.v_register[x as usize] = sum;
                 self.v_register[0xf] = match carry {
@@ -222,13 +489,14 @@ impl CPU {
                 };
             },
 
-            (8, x, y, 6) => { // Set vf to LSB of vy, store vy >> 1 in vx
-                let lsb = self.v_register[y as usize] & 0x01;
-                self.v_register[x as usize] = self.v_register[y as usize] >> 1;
+            Instruction::Shr { x, y } => { // Set vf to LSB of vy, store vy >> 1 in vx (or shift vx in place if quirk is set)
+                let source = if self.quirks.shift_vx { x } else { y };
+                let lsb = self.v_register[source as usize] & 0x01;
+                self.v_register[x as usize] = self.v_register[source as usize] >> 1;
                 self.v_register[0xf] = lsb;
             },
 
-            (8, x, y, 0x7) => { // Store vy - vx in vx, set/unset carry flag vf
+            Instruction::Subn { x, y } => { // Store vy - vx in vx, set/unset carry flag vf
                 let (sum, carry) = self.v_register[y as usize].overflowing_sub(self.v_register[x as usize]);
                 self.v_register[x as usize] = sum;
                 self.v_register[0xf] = match carry {
@@ -237,78 +505,139 @@ impl CPU {
                 };
             },
 
-            (8, x, y, 0xE) => { // Set vf to MSB of vy, store vy << 1 in vx
-                let msb = (self.v_register[y as usize] & 0x80) >> 7;
-                self.v_register[x as usize] = self.v_register[y as usize] << 1;
+            Instruction::Shl { x, y } => { // Set vf to MSB of vy, store vy << 1 in vx (or shift vx in place if quirk is set)
+                let source = if self.quirks.shift_vx { x } else { y };
+                let msb = (self.v_register[source as usize] & 0x80) >> 7;
+                self.v_register[x as usize] = self.v_register[source as usize] << 1;
                 self.v_register[0xf] = msb;
             },
 
-            (9, x, y, 0) => { // Skip if vx != vy
-                if self.v_register[x as usize] != self.v_register[y as usize] {self.program_counter += 2};
+            Instruction::SneReg { x, y } => { // Skip if vx != vy
+                if self.v_register[x as usize] != self.v_register[y as usize] {self.skip_instruction()};
             },
 
-            (0xA, _, _, _) => self.index_register = op_code & 0x0FFF, // Set i to NNN
+            Instruction::LdI(nnn) => self.index_register = nnn, // Set i to NNN
 
-            (0xB, _, _, _) => self.program_counter = (op_code & 0x0FFF).wrapping_add(self.v_register[0] as u16), // Set program counter to NNN + v0
+            Instruction::JpV0(nnn) => { // Set program counter to NNN + v0 (or XNN + vx if quirk is set)
+                let offset = if self.quirks.jump_vx { self.v_register[(nnn >> 8) as usize] } else { self.v_register[0] };
+                self.program_counter = nnn.wrapping_add(offset as u16);
+            },
 
-            (0xC, x, _, _) => { // Set vx to random number 0-255, mask with NN
-                let random_number = rand::random::<u8>();
-                let mask = (op_code & 0x00FF) as u8;
-                self.v_register[x as usize] = random_number & mask;
+            Instruction::Rnd { x, nn } => { // Set vx to random number 0-255, mask with NN
+                let random_number = self.rng.next_byte();
+                self.v_register[x as usize] = random_number & nn;
             },
 
-            (0xD, x, y, n) => { // Draw n-byte sprite on screen at (vx,vy) starting at i, set vf if a pixel is erased
+            Instruction::Drw { x, y, n } => { // Draw n-byte sprite on screen at (vx,vy) starting at i, set vf if a pixel is erased
                 // Get sprite coordinates, wrapping around screen edges
-                let sprite_x = self.v_register[x as usize] as usize % SCREEN_WIDTH;
-                let sprite_y = self.v_register[y as usize] as usize % SCREEN_HEIGHT;
+                let width = self.display.width();
+                let height = self.display.height();
+                let sprite_x = self.v_register[x as usize] as usize % width;
+                let sprite_y = self.v_register[y as usize] as usize % height;
+
+                // In SUPER-CHIP mode, DXY0 draws a 16x16 sprite from 32 bytes
+                let large_sprite = n == 0 && self.quirks.schip_opcodes;
+                let sprite_width = if large_sprite { LARGE_SPRITE_WIDTH } else { SPRITE_WIDTH };
+
+                // Every selected plane's sprite has to fit in memory before any of them are drawn
+                let sprite_bytes = if large_sprite { LARGE_SPRITE_HEIGHT * 2 } else { n as usize };
+                let planes = (0..NUM_PLANES).filter(|p| self.selected_planes & (1 << p) != 0).count();
+                let sprite_end = self.index_register as usize + sprite_bytes * planes;
+                if sprite_end > self.ram.len() {
+                    return Err(CoreError::IndexRegisterError { index: (sprite_end - 1) as u16 });
+                }
+
                 let mut collide = false;
+                let mut collided_rows: u8 = 0;
+                let mut sprite_address = self.index_register as usize;
+
+                // Each selected plane gets its own sprite, stored one after the other starting at i
+                for plane in (0..NUM_PLANES).map(|p| 1 << p).filter(|p| self.selected_planes & p != 0) {
+                    // Copy sprite from RAM as left-aligned 16-bit rows. Uses more memory than just reading from RAM, but should make code cleaner
+                    let mut sprite: Vec<u16> = Vec::with_capacity(LARGE_SPRITE_HEIGHT);
+                    if large_sprite {
+                        for i in 0..LARGE_SPRITE_HEIGHT {
+                            let address = sprite_address + (i * 2);
+                            sprite.push((self.ram[address] as u16) << 8 | self.ram[address + 1] as u16);
+                        }
+                    } else {
+                        for i in 0..(n as usize) {
+                            sprite.push((self.ram[sprite_address + i] as u16) << 8);
+                        }
+                    }
+                    sprite_address += sprite.len() * (sprite_width / 8);
 
-                // Copy sprite from RAM. Uses more memory than just reading from RAM, but should make code cleaner
-                let mut sprite: Vec<u8> = Vec::with_capacity(SPRITE_BYTES_MAX);
-                for i in 0..(n as usize) {
-                    sprite.push(self.ram[self.index_register as usize + i]);
-                }
+                    for (row, sprite_row) in sprite.iter().enumerate() {
+                        let mut row_collide = false;
 
-                for byte_row in 0..(sprite.len() as u8) {
-                    for bit_col in 0..(SPRITE_WIDTH as u8) {
-                        if ((0b10000000 >> bit_col) & sprite[byte_row as usize]) != 0 { // Sprite pixel is 1
-                            let pixel_x = sprite_x + bit_col as usize;
-                            let pixel_y = sprite_y + byte_row as usize;
-                            
-                            if pixel_x < SCREEN_WIDTH && pixel_y < SCREEN_HEIGHT { // Clip sprite pixels if off screen
-                                let display_buffer_index = (pixel_y * SCREEN_WIDTH) + pixel_x;
-                                collide |= self.display_buffer[display_buffer_index]; // If display pixel is already 1, then there is a collision
-                                self.display_buffer[display_buffer_index] ^= true; // XOR sprite pixel and display pixel
+                        for col in 0..sprite_width {
+                            if ((0x8000 >> col) & sprite_row) != 0 { // Sprite pixel is 1
+                                let mut pixel_x = sprite_x + col;
+                                let mut pixel_y = sprite_y + row;
+
+                                if !self.quirks.clip_sprites { // Wrap sprite pixels around to the other side instead of clipping
+                                    pixel_x %= width;
+                                    pixel_y %= height;
+                                }
+
+                                if pixel_x < width && pixel_y < height { // Clip sprite pixels if off screen
+                                    // XOR sprite pixel and display pixel. If display pixel was already 1, then there is a collision
+                                    row_collide |= self.display.flip(pixel_x, pixel_y, plane);
+                                }
                             }
                         }
+
+                        let row_clipped = self.quirks.clip_sprites && sprite_y + row >= height;
+                        if row_collide || row_clipped {
+                            collided_rows += 1;
+                        }
+                        collide |= row_collide;
                     }
                 }
 
-                match collide {
-                    true => self.v_register[0xF] = 1,
-                    false => self.v_register[0xF] = 0,
+                // SUPER-CHIP in hires mode counts the rows that collided or were clipped off the bottom
+                // Everything else sets vf to 1 if any pixel collided
+                if self.quirks.count_collision_rows && self.display.is_hires() {
+                    self.v_register[0xF] = collided_rows;
+                } else {
+                    self.v_register[0xF] = collide as u8;
                 }
 
                 self.display_update_flag = true;
+                self.wait_for_vblank = self.quirks.display_wait;
             },
 
-            (0xE, x, 0x9, 0xE) => { // Skip if vx key is pressed
+            Instruction::Skp(x) => { // Skip if vx key is pressed
                 let key = self.v_register[x as usize] as usize;
                 if self.key_states[key] {
-                    self.program_counter += 2;
+                    self.skip_instruction();
                 }
             },
 
-            (0xE, x, 0xA, 1) => { // Skip if vx key is not pressed
+            Instruction::Sknp(x) => { // Skip if vx key is not pressed
                 let key = self.v_register[x as usize] as usize;
-                if self.key_states[key] == false {
-                    self.program_counter += 2;
+                if !self.key_states[key] {
+                    self.skip_instruction();
                 }
             },
 
-            (0xF, x, 0, 7) => self.v_register[x as usize] = self.delay_timer, // Set vx to value of delay timer
+            Instruction::LongI if self.quirks.xo_chip_opcodes => { // Set i to the 16-bit address in the next word
+                self.index_register = self.fetch()?;
+            },
+
+            Instruction::Plane(n) if self.quirks.xo_chip_opcodes && (n as usize) < NUM_COLORS => self.selected_planes = n, // Select drawing planes with bitmask n
+
+            Instruction::Audio if self.quirks.xo_chip_opcodes => { // Load 16-byte audio pattern from ram starting at i
+                let start = self.index_register as usize;
+                if start + AUDIO_PATTERN_BYTES > self.ram.len() {
+                    return Err(CoreError::IndexRegisterError { index: self.index_register });
+                }
+                self.audio_pattern.copy_from_slice(&self.ram[start..start + AUDIO_PATTERN_BYTES]);
+            },
 
-            (0xF, x, 0, 0xA) => { // Wait for a key press, store key in vx
+            Instruction::LdVxDt(x) => self.v_register[x as usize] = self.delay_timer, // Set vx to value of delay timer
+
+            Instruction::LdVxK(x) => { // Wait for a key press, store key in vx
                 // Check for first pressed key
                 if self.wait_for_press {
                     for i in 0..self.key_states.len() {
@@ -321,23 +650,30 @@ impl CPU {
                     self.program_counter -= 2;
                 }
                 // Check for key to be released
-                if !self.wait_for_press {
-                    if !self.key_states[self.pressed_key as usize] {
-                        self.wait_for_press = true;
-                        self.v_register[x as usize] = self.pressed_key;
-                    }
+                if !self.wait_for_press && !self.key_states[self.pressed_key as usize] {
+                    self.wait_for_press = true;
+                    self.v_register[x as usize] = self.pressed_key;
                 }
             },
 
-            (0xF, x, 1, 5) => self.delay_timer = self.v_register[x as usize], // Set delay timer to value in vx
+            Instruction::LdDtVx(x) => self.delay_timer = self.v_register[x as usize], // Set delay timer to value in vx
+
+            Instruction::LdStVx(x) => self.sound_timer = self.v_register[x as usize], // Set sound timer to value in vx
+
+            Instruction::AddI(x) => self.index_register = self.index_register.wrapping_add(self.v_register[x as usize] as u16), // Set i to i + vx
 
-            (0xF, x, 1, 8) => self.sound_timer = self.v_register[x as usize], // Set sound timer to value in vx
+            Instruction::LdF(x) => self.index_register = FONT_ADDRESS_OFFSET + (self.v_register[x as usize] as u16 & 0xF) * FONT_BYTES, // Set i to address of sprite for digit vx
 
-            (0xF, x, 1, 0xE) => self.index_register = self.index_register.wrapping_add(self.v_register[x as usize] as u16), // Set i to i + vx
+            Instruction::LdHf(x) if self.quirks.schip_opcodes => { // Set i to address of large sprite for digit vx
+                self.index_register = LARGE_FONT_ADDRESS_OFFSET + (self.v_register[x as usize] as u16 & 0xF) * LARGE_FONT_BYTES;
+            },
 
-            (0xF, x, 2, 9) => self.index_register = (FONT_ADDRESS_OFFSET as u16) + (self.v_register[x as usize] as u16) * 5, // Set i to address of sprite for digit vx
+            Instruction::Pitch(x) if self.quirks.xo_chip_opcodes => self.pitch = self.v_register[x as usize], // Set audio pattern playback pitch to vx
 
-            (0xF, x, 3, 3) => { // Decode BCD digits of vx and save to addresses i, i+1, and i+2
+            Instruction::LdB(x) => { // Decode BCD digits of vx and save to addresses i, i+1, and i+2
+                if self.index_register as usize + 2 >= self.ram.len() {
+                    return Err(CoreError::IndexRegisterError { index: self.index_register.wrapping_add(2) });
+                }
                 let one = self.v_register[x as usize] % 10;
                 let ten = self.v_register[x as usize] % 100 - one;
                 let hundred = self.v_register[x as usize] - ten - one;
@@ -346,30 +682,78 @@ impl CPU {
                 self.ram[self.index_register as usize + 2] = one;
             },
 
-            (0xF, x, 5, 5) => { // Copy v[0..=x] to ram[i..=i+x], set i to i+x+1
-                if self.index_register + x >= RAM_SIZE as u16 {
-                    return Err(CoreError::IndexRegisterError { index: (self.index_register + x) });
+            Instruction::LdIVx(x) => { // Copy v[0..=x] to ram[i..=i+x], set i to i+x+1 if quirk is set
+                if self.index_register as usize + x as usize >= self.ram.len() {
+                    return Err(CoreError::IndexRegisterError { index: self.index_register.wrapping_add(x as u16) });
                 }
                 for i in 0..=(x as usize) {
                     self.ram[self.index_register as usize + i] = self.v_register[i];
                 }
-                self.index_register += x + 1;
+                if self.quirks.memory_increment {
+                    self.index_register = self.index_register.wrapping_add(x as u16 + 1);
+                }
             },
 
-            (0xF, x, 6, 5) => { // Copy ram[i..=i+x] to v[0..=x], set i to i+x+1
-                if self.index_register + x >= RAM_SIZE as u16 {
-                    return Err(CoreError::IndexRegisterError { index: (self.index_register + x) });
+            Instruction::LdVxI(x) => { // Copy ram[i..=i+x] to v[0..=x], set i to i+x+1 if quirk is set
+                if self.index_register as usize + x as usize >= self.ram.len() {
+                    return Err(CoreError::IndexRegisterError { index: self.index_register.wrapping_add(x as u16) });
                 }
                 for i in 0..=(x as usize) {
                     self.v_register[i] = self.ram[self.index_register as usize + i];
                 }
-                self.index_register += x + 1;
+                if self.quirks.memory_increment {
+                    self.index_register = self.index_register.wrapping_add(x as u16 + 1);
+                }
             },
 
-            (_, _, _, _) => return Err(CoreError::OpcodeError { opcode: (op_code) }),
+            Instruction::LdRVx(x) if self.quirks.schip_opcodes && (x as usize) < self.num_rpl_flags() => { // Copy v[0..=x] to RPL user flags, and persist them
+                let count = x as usize + 1;
+                self.rpl_flags[..count].copy_from_slice(&self.v_register[..count]);
+                let num_flags = self.num_rpl_flags();
+                if let Some(storage) = self.flag_storage.as_mut() {
+                    storage.save(&self.rpl_flags[..num_flags]).map_err(|source| CoreError::FlagStorageError { source })?;
+                }
+            },
+
+            Instruction::LdVxR(x) if self.quirks.schip_opcodes && (x as usize) < self.num_rpl_flags() => { // Copy RPL user flags to v[0..=x]
+                let count = x as usize + 1;
+                self.v_register[..count].copy_from_slice(&self.rpl_flags[..count]);
+            },
+
+            _ => return Err(CoreError::OpcodeError { opcode: op_code }),
         }
 
-        return execute_result;
+        execute_result
+    }
+
+    // SUPER-CHIP 1.0 scrolls by hires pixels even in lores mode, so the distance is halved
+    fn scroll_distance(&self, pixels: usize) -> usize {
+        if self.quirks.lores_half_scroll && !self.display.is_hires() {
+            pixels / 2
+        } else {
+            pixels
+        }
+    }
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// XO-CHIP has a 64 KiB address space, everything else has 4 KiB
+fn memory_size(quirks: &Quirks) -> usize {
+    if quirks.xo_chip_opcodes { XO_RAM_SIZE } else { RAM_SIZE }
+}
+
+// Register indices from x to y inclusive, counting down if x > y
+fn register_range(x: u8, y: u8) -> Box<dyn Iterator<Item = usize>> {
+    let (x, y) = (x as usize, y as usize);
+    if x <= y {
+        Box::new(x..=y)
+    } else {
+        Box::new((y..=x).rev())
     }
 }
 
@@ -388,6 +772,21 @@ fn slice_u16(word: u16) -> (u16, u16, u16, u16) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::{cell::RefCell, io, rc::Rc};
+
+    // Flag storage that can be shared between CPU instances
+    struct SharedFlags(Rc<RefCell<Option<Vec<u8>>>>);
+
+    impl FlagStorage for SharedFlags {
+        fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+            Ok(self.0.borrow().clone())
+        }
+
+        fn save(&mut self, flags: &[u8]) -> io::Result<()> {
+            *self.0.borrow_mut() = Some(flags.to_vec());
+            Ok(())
+        }
+    }
 
     #[test]
     fn fetch_opcode() {
@@ -426,6 +825,74 @@ mod tests {
         print!("{:?}", load_result);
         assert!(load_result.is_err());
     }
+    #[test]
+    fn cycle_timing() {
+        let mut cpu = CPU::new();
+        assert!(cpu.load_rom_from_buffer(&[0x60, 0x01, 0x30, 0x01, 0x00, 0x00, 0x60, 0x02]).is_ok());
+        assert_eq!(cpu.cycle().unwrap(), 1);
+        cpu.set_timing(Timing::Vip);
+        assert_eq!(cpu.cycle().unwrap(), timing::vip_cycles(0x3001, true, 0));
+        assert_eq!(cpu.program_counter, START_ADDRESS + 6);
+        assert_eq!(cpu.cycle().unwrap(), timing::vip_cycles(0x6002, false, 0));
+    }
+
+    #[test]
+    fn cycle_timing_long_skip() {
+        // Skipping over XO-CHIP's four byte F000 NNNN still counts as a taken skip
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        cpu.set_timing(Timing::Vip);
+        assert!(cpu.load_rom_from_buffer(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34]).is_ok());
+        assert_eq!(cpu.cycle().unwrap(), timing::vip_cycles(0x3000, true, 0));
+        assert_eq!(cpu.program_counter, START_ADDRESS + 6);
+    }
+
+    #[test]
+    fn accessors() {
+        // Set I, v0 and both timers, then call a subroutine
+        let rom = [0xA1, 0x23, 0x60, 0x05, 0xF0, 0x15, 0xF0, 0x18, 0x22, 0x0C, 0x00, 0x00, 0x00, 0xE0];
+        let mut cpu = CPU::new();
+        assert!(cpu.load_rom_from_buffer(&rom).is_ok());
+        for _ in 0..5 {
+            assert!(cpu.cycle().is_ok());
+        }
+        assert_eq!(cpu.program_counter(), START_ADDRESS + 12);
+        assert_eq!(cpu.index_register(), 0x123);
+        assert_eq!(cpu.v_registers()[0], 5);
+        assert_eq!(cpu.stack(), &[START_ADDRESS + 10]);
+        assert_eq!((cpu.delay_timer(), cpu.sound_timer()), (5, 5));
+        assert_eq!(cpu.memory().len(), RAM_SIZE);
+        assert_eq!(&cpu.memory()[START_ADDRESS as usize..START_ADDRESS as usize + 2], &[0xA1, 0x23]);
+    }
+
+    #[test]
+    fn display_wait() {
+        // Draw, then add 1 to v0
+        let rom = [0xD0, 0x01, 0x70, 0x01];
+        let mut cpu = CPU::new();
+        assert!(cpu.load_rom_from_buffer(&rom).is_ok());
+        assert!(cpu.cycle().is_ok());
+        assert!(cpu.cycle().is_ok());
+        assert_eq!(cpu.program_counter, START_ADDRESS + 2);
+        cpu.tick_timers();
+        assert!(cpu.cycle().is_ok());
+        assert_eq!(cpu.v_register[0], 1);
+
+        let mut cpu = CPU::with_quirks(Quirks::SCHIP_1_1);
+        assert!(cpu.load_rom_from_buffer(&rom).is_ok());
+        assert!(cpu.cycle().is_ok());
+        assert!(cpu.cycle().is_ok());
+        assert_eq!(cpu.v_register[0], 1);
+    }
+
+    #[test]
+    fn write_memory() {
+        let mut cpu = CPU::new();
+        assert!(cpu.write_memory(0x1FF, &[1, 2]).is_ok());
+        assert_eq!(cpu.ram[0x1FF..0x201], [1, 2]);
+        assert!(cpu.write_memory(0xFFF, &[1, 2]).is_err());
+        assert_eq!(cpu.ram[0xFFF], 0);
+    }
+
     #[test]
     fn slice_u16_test() {
         let word: u16 = 0xDEAD;
@@ -454,19 +921,82 @@ mod tests {
     #[test]
     fn op_00e0() {
         let mut cpu = CPU::new();
-        cpu.display_buffer = [true; SCREEN_BUFF_SIZE];
+        cpu.display.buffer.fill(1);
         assert!(cpu.execute(0x00E0).is_ok());
-        assert_eq!(cpu.display_buffer[SCREEN_WIDTH], false);
+        assert_eq!(cpu.display.pixels()[SCREEN_WIDTH], 0);
         assert!(cpu.display_update_flag);
     }
 
+    #[test]
+    fn op_00fe_00ff() {
+        let mut cpu = CPU::with_quirks(Quirks::SCHIP_1_1);
+        assert!(cpu.execute(0x00FF).is_ok());
+        assert_eq!(cpu.get_display().width(), HIRES_SCREEN_WIDTH);
+        assert_eq!(cpu.get_display().height(), HIRES_SCREEN_HEIGHT);
+        cpu.index_register = FONT_ADDRESS_OFFSET;
+        cpu.v_register[0] = 100;
+        cpu.v_register[1] = 40;
+        assert!(cpu.execute(0xD015).is_ok());
+        assert!(cpu.get_display().pixel(100, 40));
+        assert!(cpu.execute(0x00FE).is_ok());
+        assert_eq!(cpu.get_display().width(), SCREEN_WIDTH);
+        assert_eq!(cpu.get_display().height(), SCREEN_HEIGHT);
+        // Not available on the original interpreter
+        let mut cpu = CPU::new();
+        assert!(cpu.execute(0x00FF).is_err());
+    }
+
+    #[test]
+    fn op_00cn() {
+        let mut cpu = CPU::with_quirks(Quirks::SCHIP_1_1);
+        cpu.display.flip(0, 0, 1);
+        assert!(cpu.execute(0x00C3).is_ok());
+        assert!(cpu.get_display().pixel(0, 3));
+        assert!(cpu.display_update_flag);
+        let mut cpu = CPU::with_quirks(Quirks::SCHIP_1_0);
+        cpu.display.flip(0, 0, 1);
+        assert!(cpu.execute(0x00C4).is_ok());
+        assert!(cpu.get_display().pixel(0, 2)); // Half distance in lores
+        assert!(cpu.execute(0x00FF).is_ok());
+        cpu.display.flip(0, 0, 1);
+        assert!(cpu.execute(0x00C4).is_ok());
+        assert!(cpu.get_display().pixel(0, 4)); // Full distance in hires
+    }
+
+    #[test]
+    fn op_00dn() {
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        cpu.display.flip(0, 5, 1);
+        assert!(cpu.execute(0x00D3).is_ok());
+        assert!(cpu.get_display().pixel(0, 2));
+        let mut cpu = CPU::with_quirks(Quirks::SCHIP_1_1);
+        assert!(cpu.execute(0x00D3).is_err());
+    }
+
+    #[test]
+    fn op_00fb_00fc() {
+        let mut cpu = CPU::with_quirks(Quirks::SCHIP_1_1);
+        cpu.display.flip(8, 0, 1);
+        assert!(cpu.execute(0x00FB).is_ok());
+        assert!(cpu.get_display().pixel(12, 0));
+        assert!(cpu.execute(0x00FC).is_ok());
+        assert!(cpu.execute(0x00FC).is_ok());
+        assert!(cpu.get_display().pixel(4, 0));
+        let mut cpu = CPU::with_quirks(Quirks::SCHIP_1_0);
+        cpu.display.flip(8, 0, 1);
+        assert!(cpu.execute(0x00FB).is_ok());
+        assert!(cpu.get_display().pixel(10, 0));
+        let mut cpu = CPU::new();
+        assert!(cpu.execute(0x00FB).is_err());
+    }
+
     #[test]
     fn op_00ee() {
         let mut cpu = CPU::new();
         cpu.stack.push(0x0210);
         assert!(cpu.execute(0x00EE).is_ok());
         assert_eq!(cpu.program_counter, 0x0210);
-        assert!(cpu.stack.len() == 0);
+        assert!(cpu.stack.is_empty());
     }
     
     #[test]
@@ -485,6 +1015,16 @@ mod tests {
         assert_eq!(cpu.stack[0], 0x0222);
     }
 
+    #[test]
+    fn op_2nnn_stack_full() {
+        let mut cpu = CPU::new();
+        for _ in 0..STACK_SIZE {
+            assert!(cpu.execute(0x2234).is_ok());
+        }
+        assert!(matches!(cpu.execute(0x2234), Err(CoreError::StackFullError)));
+        assert_eq!(cpu.stack.len(), STACK_SIZE);
+    }
+
     #[test]
     fn op_3xnn() {
         let mut cpu = CPU::new();
@@ -510,6 +1050,30 @@ mod tests {
         assert_eq!(cpu.program_counter, START_ADDRESS + 2);
     }
 
+    #[test]
+    fn op_5xy2() {
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        cpu.v_register[2..=5].copy_from_slice(&[1, 2, 3, 4]);
+        cpu.index_register = START_ADDRESS;
+        assert!(cpu.execute(0x5252).is_ok());
+        assert_eq!(cpu.ram[(START_ADDRESS as usize)..(START_ADDRESS as usize + 4)], [1, 2, 3, 4]);
+        assert_eq!(cpu.index_register, START_ADDRESS);
+        assert!(cpu.execute(0x5522).is_ok());
+        assert_eq!(cpu.ram[(START_ADDRESS as usize)..(START_ADDRESS as usize + 4)], [4, 3, 2, 1]);
+        let mut cpu = CPU::new();
+        assert!(cpu.execute(0x5252).is_err());
+    }
+
+    #[test]
+    fn op_5xy3() {
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        cpu.ram[(START_ADDRESS as usize)..(START_ADDRESS as usize + 3)].copy_from_slice(&[7, 8, 9]);
+        cpu.index_register = START_ADDRESS;
+        assert!(cpu.execute(0x5AC3).is_ok());
+        assert_eq!(cpu.v_register[0xA..=0xC], [7, 8, 9]);
+        assert!(cpu.execute(0x5311).is_err()); // 5XY1 is not an instruction
+    }
+
     #[test]
     fn op_6xnn() {
         let mut cpu = CPU::new();
@@ -542,6 +1106,18 @@ mod tests {
         assert_eq!(cpu.v_register[0], 0xFF);
     }
 
+    #[test]
+    fn op_8xy1_vf_reset_quirk() {
+        let mut cpu = CPU::new();
+        cpu.v_register[0xF] = 0x01;
+        assert!(cpu.execute(0x8011).is_ok());
+        assert_eq!(cpu.v_register[0xF], 0x00);
+        let mut cpu = CPU::with_quirks(Quirks::SCHIP_1_1);
+        cpu.v_register[0xF] = 0x01;
+        assert!(cpu.execute(0x8011).is_ok());
+        assert_eq!(cpu.v_register[0xF], 0x01);
+    }
+
     #[test]
     fn op_8xy2() {
         let mut cpu = CPU::new();
@@ -583,7 +1159,17 @@ mod tests {
     #[test]
     fn op_8xy6() {
         let mut cpu = CPU::new();
-        cpu.v_register[0] = 0xAB; // vy
+        cpu.v_register[1] = 0xAB; // vy
+        assert!(cpu.execute(0x8016).is_ok());
+        assert_eq!(cpu.v_register[0], 0x55);
+        assert_eq!(cpu.v_register[0xf], 0x01);
+    }
+
+    #[test]
+    fn op_8xy6_shift_quirk() {
+        let mut cpu = CPU::with_quirks(Quirks::CHIP_48);
+        cpu.v_register[0] = 0xAB; // vx
+        cpu.v_register[1] = 0x10; // vy, ignored
         assert!(cpu.execute(0x8016).is_ok());
         assert_eq!(cpu.v_register[0], 0x55);
         assert_eq!(cpu.v_register[0xf], 0x01);
@@ -630,14 +1216,37 @@ mod tests {
         let mut cpu = CPU::new();
         cpu.v_register[0] = 0x0010;
         assert!(cpu.execute(0xB321).is_ok());
-        assert_eq!(cpu.index_register, 0x0331);
+        assert_eq!(cpu.program_counter, 0x0331);
+    }
+
+    #[test]
+    fn op_bnnn_jump_quirk() {
+        let mut cpu = CPU::with_quirks(Quirks::CHIP_48);
+        cpu.v_register[0] = 0x0010;
+        cpu.v_register[3] = 0x0020;
+        assert!(cpu.execute(0xB321).is_ok());
+        assert_eq!(cpu.program_counter, 0x0341);
     }
 
     #[test]
     fn op_cxnn() {
         let mut cpu = CPU::new();
-        assert!(cpu.execute(0xcAFF).is_ok());
-        println!("{}", cpu.v_register[0xA]);
+        cpu.set_random_source(Box::new(ScriptedRandom::new(&[0xA5, 0xFF])));
+        assert!(cpu.execute(0xCAFF).is_ok());
+        assert_eq!(cpu.v_register[0xA], 0xA5);
+        assert!(cpu.execute(0xCA0F).is_ok());
+        assert_eq!(cpu.v_register[0xA], 0x0F);
+
+        // Same seed, same numbers
+        let mut a = CPU::new();
+        let mut b = CPU::new();
+        a.seed_random(42);
+        b.seed_random(42);
+        for _ in 0..8 {
+            assert!(a.execute(0xC0FF).is_ok());
+            assert!(b.execute(0xC0FF).is_ok());
+            assert_eq!(a.v_register[0], b.v_register[0]);
+        }
     }
 
     #[test]
@@ -645,12 +1254,55 @@ mod tests {
         let mut cpu = CPU::new();
         cpu.index_register = FONT_ADDRESS_OFFSET;
         assert!(cpu.execute(0xD015).is_ok());
-        assert_eq!(cpu.display_buffer[0], true);
-        assert_eq!(cpu.display_buffer[SCREEN_WIDTH + 1], false);
-        assert_eq!(cpu.display_buffer[(SCREEN_WIDTH * 4) + 3], true);
+        assert_eq!(cpu.display.pixels()[0], 1);
+        assert_eq!(cpu.display.pixels()[SCREEN_WIDTH + 1], 0);
+        assert_eq!(cpu.display.pixels()[(SCREEN_WIDTH * 4) + 3], 1);
         assert!(cpu.display_update_flag);
     }
 
+    #[test]
+    fn op_dxyn_wrap_quirk() {
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        cpu.index_register = FONT_ADDRESS_OFFSET;
+        cpu.v_register[0] = (SCREEN_WIDTH - 2) as u8;
+        assert!(cpu.execute(0xD011).is_ok());
+        assert_eq!(cpu.display.pixels()[SCREEN_WIDTH - 1], 1);
+        assert_eq!(cpu.display.pixels()[1], 1); // Wrapped to the left edge
+        let mut cpu = CPU::new();
+        cpu.index_register = FONT_ADDRESS_OFFSET;
+        cpu.v_register[0] = (SCREEN_WIDTH - 2) as u8;
+        assert!(cpu.execute(0xD011).is_ok());
+        assert_eq!(cpu.display.pixels()[1], 0); // Clipped
+    }
+
+    #[test]
+    fn op_dxy0() {
+        let mut cpu = CPU::with_quirks(Quirks::SCHIP_1_1);
+        assert!(cpu.execute(0x00FF).is_ok());
+        for i in 0..32 {
+            cpu.ram[START_ADDRESS as usize + i] = 0xFF;
+        }
+        cpu.index_register = START_ADDRESS;
+        assert!(cpu.execute(0xD010).is_ok());
+        assert!(cpu.get_display().pixel(15, 15));
+        assert!(!cpu.get_display().pixel(16, 16));
+        assert_eq!(cpu.v_register[0xF], 0);
+        // Overlap 4 rows of the previous sprite
+        cpu.v_register[1] = 12;
+        assert!(cpu.execute(0xD010).is_ok());
+        assert_eq!(cpu.v_register[0xF], 4);
+        // 6 rows hang off the bottom of the screen
+        cpu.v_register[1] = (HIRES_SCREEN_HEIGHT - 10) as u8;
+        cpu.v_register[0] = 32;
+        assert!(cpu.execute(0xD010).is_ok());
+        assert_eq!(cpu.v_register[0xF], 6);
+        // Original interpreter draws nothing
+        let mut cpu = CPU::new();
+        cpu.index_register = FONT_ADDRESS_OFFSET;
+        assert!(cpu.execute(0xD010).is_ok());
+        assert!(cpu.get_display().pixels().iter().all(|p| *p == 0));
+    }
+
     #[test]
     fn op_ex9e() {
         let mut cpu = CPU::new();
@@ -675,6 +1327,112 @@ mod tests {
         assert_eq!(cpu.program_counter, START_ADDRESS + 2);
     }
 
+    #[test]
+    fn op_f000() {
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        assert!(cpu.load_rom_from_buffer(&[0xF0, 0x00, 0xBE, 0xEF, 0x00, 0xE0]).is_ok());
+        assert!(cpu.cycle().is_ok());
+        assert_eq!(cpu.index_register, 0xBEEF);
+        assert_eq!(cpu.program_counter, START_ADDRESS + 4);
+        let mut cpu = CPU::new();
+        assert!(cpu.execute(0xF000).is_err());
+    }
+
+    #[test]
+    fn skip_long_instruction() {
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        assert!(cpu.load_rom_from_buffer(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xE0]).is_ok());
+        assert!(cpu.cycle().is_ok());
+        assert_eq!(cpu.program_counter, START_ADDRESS + 6);
+        // Without XO-CHIP, F000 is just a (bad) 2-byte instruction
+        let mut cpu = CPU::new();
+        assert!(cpu.load_rom_from_buffer(&[0x30, 0x00, 0xF0, 0x00, 0x12, 0x34, 0x00, 0xE0]).is_ok());
+        assert!(cpu.cycle().is_ok());
+        assert_eq!(cpu.program_counter, START_ADDRESS + 4);
+    }
+
+    #[test]
+    fn op_fn01() {
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        assert!(cpu.execute(0xF301).is_ok());
+        assert_eq!(cpu.selected_planes, 3);
+        assert!(cpu.execute(0xF401).is_err());
+    }
+
+    #[test]
+    fn draw_planes() {
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        cpu.ram[START_ADDRESS as usize] = 0xC0; // Plane 1 sprite
+        cpu.ram[START_ADDRESS as usize + 1] = 0x60; // Plane 2 sprite
+        cpu.index_register = START_ADDRESS;
+        assert!(cpu.execute(0xF301).is_ok());
+        assert!(cpu.execute(0xD011).is_ok());
+        assert_eq!(cpu.get_display().pixels()[0..4], [1, 3, 2, 0]);
+        assert_eq!(cpu.v_register[0xF], 0);
+        // Clear only plane 2
+        assert!(cpu.execute(0xF201).is_ok());
+        assert!(cpu.execute(0x00E0).is_ok());
+        assert_eq!(cpu.get_display().pixels()[0..4], [1, 1, 0, 0]);
+        // Plane 2 alone draws the first sprite, and doesn't collide with plane 1
+        assert!(cpu.execute(0xD011).is_ok());
+        assert_eq!(cpu.get_display().pixels()[0..4], [3, 3, 0, 0]);
+        assert_eq!(cpu.v_register[0xF], 0);
+        assert!(cpu.execute(0xD011).is_ok());
+        assert_eq!(cpu.v_register[0xF], 1);
+    }
+
+    #[test]
+    fn op_f002_fx3a() {
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        for i in 0..AUDIO_PATTERN_BYTES {
+            cpu.ram[START_ADDRESS as usize + i] = i as u8;
+        }
+        cpu.index_register = START_ADDRESS;
+        assert!(cpu.execute(0xF002).is_ok());
+        assert_eq!(cpu.audio_pattern[15], 15);
+        cpu.v_register[4] = 112;
+        assert!(cpu.execute(0xF43A).is_ok());
+        assert_eq!(cpu.pitch, 112);
+    }
+
+    #[test]
+    fn xo_chip_address_space() {
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        assert!(cpu.load_rom_from_buffer(&vec![0xA; 0x8000]).is_ok());
+        cpu.index_register = 0xFFF0;
+        cpu.v_register[0] = 0x42;
+        assert!(cpu.execute(0xF055).is_ok());
+        assert_eq!(cpu.ram[0xFFF0], 0x42);
+        cpu.index_register = 0xFFFE;
+        assert!(cpu.execute(0xF555).is_err());
+    }
+
+    #[test]
+    fn index_near_top_of_memory() {
+        // A 16x16 sprite needs 32 bytes, or 64 with both planes selected
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        cpu.index_register = 0xFFE0;
+        assert!(cpu.execute(0xD010).is_ok());
+        cpu.index_register = 0xFFFF;
+        assert!(matches!(cpu.execute(0xD010), Err(CoreError::IndexRegisterError { .. })));
+        cpu.index_register = 0xFFE0;
+        assert!(cpu.execute(0xF301).is_ok());
+        assert!(matches!(cpu.execute(0xD010), Err(CoreError::IndexRegisterError { .. })));
+        assert!(cpu.execute(0xD01F).is_ok());
+        cpu.index_register = 0xFFF0;
+        assert!(matches!(cpu.execute(0xD01F), Err(CoreError::IndexRegisterError { .. })));
+
+        cpu.index_register = 0xFFFD;
+        assert!(cpu.execute(0xF033).is_ok());
+        cpu.index_register = 0xFFFE;
+        assert!(matches!(cpu.execute(0xF033), Err(CoreError::IndexRegisterError { index: 0 })));
+
+        let mut cpu = CPU::new();
+        cpu.index_register = 0xFFC;
+        assert!(cpu.execute(0xD014).is_ok());
+        assert!(cpu.execute(0xD015).is_err());
+    }
+
     #[test]
     fn op_fx07() {
         let mut cpu = CPU::new();
@@ -734,6 +1492,20 @@ mod tests {
         assert_eq!(cpu.index_register, 20);
     }
 
+    #[test]
+    fn op_fx30() {
+        let mut cpu = CPU::with_quirks(Quirks::SCHIP_1_1);
+        cpu.v_register[0xC] = 0x4;
+        assert!(cpu.execute(0xFC30).is_ok());
+        assert_eq!(cpu.index_register, LARGE_FONT_ADDRESS_OFFSET + 40);
+        assert_eq!(cpu.ram[cpu.index_register as usize..(cpu.index_register + 10) as usize], FONT_SET_LARGE[40..50]);
+        // Small font is still where FX29 expects it
+        assert!(cpu.execute(0xFC29).is_ok());
+        assert_eq!(cpu.ram[cpu.index_register as usize..(cpu.index_register + 5) as usize], FONT_SET_1[20..25]);
+        let mut cpu = CPU::new();
+        assert!(cpu.execute(0xFC30).is_err());
+    }
+
     #[test]
     fn op_fx33() {
         let mut cpu = CPU::new();
@@ -744,7 +1516,7 @@ mod tests {
         cpu.v_register[0xC] = 205;
         assert!(cpu.execute(0xFC33).is_ok());
         assert_eq!(cpu.ram[(START_ADDRESS as usize)..=(START_ADDRESS as usize + 2)], [2, 0, 5]);
-        cpu.v_register[0xC] = 002;
+        cpu.v_register[0xC] = 2;
         assert!(cpu.execute(0xFC33).is_ok());
         assert_eq!(cpu.ram[(START_ADDRESS as usize)..=(START_ADDRESS as usize + 2)], [0, 0, 2]);
         cpu.v_register[0xC] = 140;
@@ -759,6 +1531,7 @@ mod tests {
         cpu.index_register = START_ADDRESS;
         let _ = cpu.execute(0xFC55);
         assert_eq!(cpu.ram[(START_ADDRESS as usize)..=(START_ADDRESS as usize + 0xC)], [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13]);
+        assert_eq!(cpu.index_register, START_ADDRESS + 0xD);
         cpu.index_register = RAM_SIZE as u16 - 5;
         let execute_result = cpu.execute(0xFC55);
         print!("{:?}", execute_result);
@@ -779,4 +1552,35 @@ mod tests {
         print!("{:?}", execute_result);
         assert!(execute_result.is_err());
     }
+
+    #[test]
+    fn op_fx65_memory_quirk() {
+        let mut cpu = CPU::with_quirks(Quirks::SCHIP_1_1);
+        cpu.index_register = START_ADDRESS;
+        assert!(cpu.execute(0xFC65).is_ok());
+        assert_eq!(cpu.index_register, START_ADDRESS);
+    }
+
+    #[test]
+    fn op_fx75_fx85() {
+        let saved = Rc::new(RefCell::new(None));
+        let mut cpu = CPU::with_quirks(Quirks::SCHIP_1_1);
+        assert!(cpu.set_flag_storage(Box::new(SharedFlags(saved.clone()))).is_ok());
+        cpu.v_register[0..4].copy_from_slice(&[9, 8, 7, 6]);
+        assert!(cpu.execute(0xF375).is_ok());
+        assert_eq!(saved.borrow().as_deref(), Some(&[9, 8, 7, 6, 0, 0, 0, 0][..]));
+        // Only 8 flags outside of XO-CHIP
+        assert!(cpu.execute(0xF875).is_err());
+
+        // Flags are restored in the next run
+        let mut cpu = CPU::with_quirks(Quirks::SCHIP_1_1);
+        assert!(cpu.set_flag_storage(Box::new(SharedFlags(saved.clone()))).is_ok());
+        assert!(cpu.execute(0xF285).is_ok());
+        assert_eq!(cpu.v_register[0..4], [9, 8, 7, 0]);
+
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        cpu.v_register = [1; NUM_REGISTERS];
+        assert!(cpu.execute(0xFF75).is_ok());
+        assert_eq!(cpu.rpl_flags, [1; XO_NUM_RPL_FLAGS]);
+    }
 }
@@ -0,0 +1,378 @@
+use std::fmt;
+
+use crate::core_error::CoreError;
+use crate::flags::FlagStorage;
+use crate::{Quirks, Scheduler, Timing, CPU, NUM_KEYS};
+
+// Recorded input for replaying a session exactly, stored as plain text with one item per line:
+//
+//   chip8-movie 1
+//   rom <FNV-1a 64-bit hash of the ROM, 16 hex digits>
+//   seed <random source state at power on, used as the Xorshift seed>
+//   quirks <name>=<0|1> ... (every field of Quirks, missing ones are off)
+//   timing <instruction|vip>
+//   speed <cycles per second>
+//   flags <RPL user flags at power on, as hex>
+//   key <instructions run> <key 0-f> <down|up>
+//   ...
+//   end <instructions run> <timer ticks run> <FNV-1a hash of the final save state>
+//
+// A key event applies just before the instruction after that many have run, and blank lines and
+// lines starting with # are ignored. The end line is added when recording stops, and replaying checks
+// the final state against it. Speed changes, rewinding and loading states aren't recorded, so they
+// are turned off while recording.
+const MOVIE_MAGIC: &str = "chip8-movie";
+const MOVIE_VERSION: u32 = 1;
+
+const FNV_OFFSET_BASIS: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+/// 64-bit FNV-1a hash, used to identify ROMs and compare machine states
+pub fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, byte| (hash ^ *byte as u64).wrapping_mul(FNV_PRIME))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub instruction: u64,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MovieEnd {
+    pub instructions: u64,
+    pub ticks: u64,
+    pub state_hash: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub seed: u64,
+    pub quirks: Quirks,
+    pub timing: Timing,
+    pub cycles_per_second: u32,
+    pub rpl_flags: Vec<u8>,
+    pub keys: Vec<KeyEvent>,
+    pub end: Option<MovieEnd>,
+}
+
+impl Movie {
+    /// Start recording a CPU that has just been powered on with this ROM, before anything has run
+    pub fn record(cpu: &CPU, rom: &[u8], scheduler: &Scheduler) -> Self {
+        Self {
+            rom_hash: fnv1a(rom),
+            seed: cpu.random_state(),
+            quirks: cpu.quirks(),
+            timing: cpu.timing(),
+            cycles_per_second: scheduler.cycles_per_second(),
+            rpl_flags: cpu.rpl_flags().to_vec(),
+            keys: Vec::new(),
+            end: None,
+        }
+    }
+
+    pub fn record_key(&mut self, instructions_run: u64, key: usize, pressed: bool) {
+        self.keys.push(KeyEvent { instruction: instructions_run, key: key as u8, pressed });
+    }
+
+    /// Stop recording, noting where the session ended and what state it was in
+    pub fn finish(&mut self, cpu: &CPU, scheduler: &Scheduler) {
+        self.end = Some(MovieEnd {
+            instructions: scheduler.instructions_run(),
+            ticks: scheduler.ticks_run(),
+            state_hash: fnv1a(&cpu.save_state()),
+        });
+    }
+
+    /// Power on a CPU with the recorded settings, failing if the ROM isn't the one that was recorded
+    pub fn start(&self, rom: &[u8]) -> Result<(CPU, Scheduler), CoreError> {
+        let found = fnv1a(rom);
+        if found != self.rom_hash {
+            return Err(CoreError::RomHashError { expected: self.rom_hash, found });
+        }
+
+        let mut cpu = CPU::with_quirks(self.quirks);
+        cpu.set_timing(self.timing);
+        cpu.seed_random(self.seed);
+        cpu.load_rom_from_buffer(rom).map_err(|_| CoreError::RomSizeError)?;
+        cpu.set_flag_storage(Box::new(ReplayFlags(self.rpl_flags.clone())))?;
+        Ok((cpu, Scheduler::new(self.cycles_per_second)))
+    }
+
+    /// Run the whole movie as fast as possible and check it ends in the recorded state
+    pub fn replay(&self, rom: &[u8]) -> Result<CPU, CoreError> {
+        let Some(end) = self.end else {
+            return Err(CoreError::MovieError { line: 0, reason: "recording has no end line".to_string() });
+        };
+        let (mut cpu, mut scheduler) = self.start(rom)?;
+        let mut player = MoviePlayer::new(self.clone());
+
+        while scheduler.instructions_run() < end.instructions || scheduler.ticks_run() < end.ticks {
+            scheduler.step(&mut cpu, &mut |cpu, n| player.apply(cpu, n))?;
+        }
+        // Keys released after the last instruction still count towards the final state
+        player.apply(&mut cpu, end.instructions)?;
+
+        if fnv1a(&cpu.save_state()) != end.state_hash {
+            return Err(CoreError::DesyncError { instructions: end.instructions });
+        }
+        Ok(cpu)
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, CoreError> {
+        let mut movie = Movie {
+            rom_hash: 0,
+            seed: 0,
+            quirks: Quirks::VIP,
+            timing: Timing::default(),
+            cycles_per_second: 0,
+            rpl_flags: Vec::new(),
+            keys: Vec::new(),
+            end: None,
+        };
+        let mut seen = Vec::new();
+
+        let lines = text.lines().enumerate()
+            .map(|(i, line)| (i + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+        for (number, line) in lines {
+            let error = |reason: &str| CoreError::MovieError { line: number, reason: reason.to_string() };
+            let mut words = line.split_whitespace();
+            let tag = words.next().unwrap_or_default();
+            let args: Vec<&str> = words.collect();
+
+            if seen.is_empty() && tag != MOVIE_MAGIC {
+                return Err(error("not a movie file"));
+            }
+            if tag != "key" && seen.contains(&tag) {
+                return Err(error("repeated line"));
+            }
+            if movie.end.is_some() {
+                return Err(error("line after the end"));
+            }
+
+            match (tag, args.as_slice()) {
+                (MOVIE_MAGIC, [version]) => {
+                    if version.parse::<u32>().ok() != Some(MOVIE_VERSION) {
+                        return Err(error("unsupported version"));
+                    }
+                },
+                ("rom", [hash]) => movie.rom_hash = parse_hex(hash).ok_or_else(|| error("invalid ROM hash"))?,
+                ("seed", [seed]) => movie.seed = seed.parse().map_err(|_| error("invalid seed"))?,
+                ("quirks", fields) => {
+                    movie.quirks = Quirks::VIP;
+                    for (name, _) in Quirks::VIP.fields() {
+                        movie.quirks.set_field(name, false);
+                    }
+                    for field in fields {
+                        let (name, value) = field.split_once('=').ok_or_else(|| error("invalid quirk"))?;
+                        let enabled = match value {
+                            "0" => false,
+                            "1" => true,
+                            _ => return Err(error("invalid quirk")),
+                        };
+                        if !movie.quirks.set_field(name, enabled) {
+                            return Err(error("unknown quirk"));
+                        }
+                    }
+                },
+                ("timing", [name]) => movie.timing = Timing::from_name(name).ok_or_else(|| error("unknown timing"))?,
+                ("speed", [speed]) => {
+                    movie.cycles_per_second = speed.parse().ok().filter(|s| *s > 0).ok_or_else(|| error("invalid speed"))?;
+                },
+                ("flags", [flags]) => movie.rpl_flags = parse_bytes(flags).ok_or_else(|| error("invalid flags"))?,
+                ("flags", []) => movie.rpl_flags = Vec::new(),
+                ("key", [instruction, key, state]) => {
+                    let instruction = instruction.parse().map_err(|_| error("invalid instruction count"))?;
+                    if movie.keys.last().is_some_and(|last| last.instruction > instruction) {
+                        return Err(error("keys out of order"));
+                    }
+                    let key = u8::from_str_radix(key, 16).ok().filter(|k| (*k as usize) < NUM_KEYS).ok_or_else(|| error("invalid key"))?;
+                    let pressed = match *state {
+                        "down" => true,
+                        "up" => false,
+                        _ => return Err(error("key state must be up or down")),
+                    };
+                    movie.keys.push(KeyEvent { instruction, key, pressed });
+                },
+                ("end", [instructions, ticks, hash]) => {
+                    movie.end = Some(MovieEnd {
+                        instructions: instructions.parse().map_err(|_| error("invalid instruction count"))?,
+                        ticks: ticks.parse().map_err(|_| error("invalid tick count"))?,
+                        state_hash: parse_hex(hash).ok_or_else(|| error("invalid state hash"))?,
+                    });
+                },
+                _ => return Err(error("unrecognised line")),
+            }
+            seen.push(tag);
+        }
+
+        for tag in [MOVIE_MAGIC, "rom", "seed", "quirks", "timing", "speed"] {
+            if !seen.contains(&tag) {
+                return Err(CoreError::MovieError { line: 0, reason: format!("missing {} line", tag) });
+            }
+        }
+        Ok(movie)
+    }
+}
+
+impl fmt::Display for Movie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{} {}", MOVIE_MAGIC, MOVIE_VERSION)?;
+        writeln!(f, "rom {:016x}", self.rom_hash)?;
+        writeln!(f, "seed {}", self.seed)?;
+        let quirks: Vec<String> = self.quirks.fields().iter()
+            .map(|(name, enabled)| format!("{}={}", name, *enabled as u8))
+            .collect();
+        writeln!(f, "quirks {}", quirks.join(" "))?;
+        writeln!(f, "timing {}", self.timing.name())?;
+        writeln!(f, "speed {}", self.cycles_per_second)?;
+        let flags: String = self.rpl_flags.iter().map(|b| format!("{:02x}", b)).collect();
+        writeln!(f, "flags {}", flags)?;
+        for event in self.keys.iter() {
+            writeln!(f, "key {} {:x} {}", event.instruction, event.key, if event.pressed { "down" } else { "up" })?;
+        }
+        if let Some(end) = self.end {
+            writeln!(f, "end {} {} {:016x}", end.instructions, end.ticks, end.state_hash)?;
+        }
+        Ok(())
+    }
+}
+
+// Feeds a movie's keys into the CPU at the right moments, from a Scheduler instruction hook
+pub struct MoviePlayer {
+    movie: Movie,
+    next: usize,
+}
+
+impl MoviePlayer {
+    pub fn new(movie: Movie) -> Self {
+        Self { movie, next: 0 }
+    }
+
+    pub fn movie(&self) -> &Movie {
+        &self.movie
+    }
+
+    /// True once every key in the movie has been played
+    pub fn finished(&self) -> bool {
+        self.next >= self.movie.keys.len()
+    }
+
+    /// Press and release the keys due once this many instructions have run
+    pub fn apply(&mut self, cpu: &mut CPU, instructions_run: u64) -> Result<(), CoreError> {
+        while let Some(event) = self.movie.keys.get(self.next).filter(|e| e.instruction <= instructions_run) {
+            cpu.keypress(event.key as usize, event.pressed)?;
+            self.next += 1;
+        }
+        Ok(())
+    }
+}
+
+// Starts a replay with the flags the recording started with, and keeps whatever it writes in memory
+struct ReplayFlags(Vec<u8>);
+
+impl FlagStorage for ReplayFlags {
+    fn load(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        Ok(Some(self.0.clone()))
+    }
+
+    fn save(&mut self, flags: &[u8]) -> std::io::Result<()> {
+        self.0 = flags.to_vec();
+        Ok(())
+    }
+}
+
+fn parse_hex(text: &str) -> Option<u64> {
+    u64::from_str_radix(text, 16).ok()
+}
+
+fn parse_bytes(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) || !text.is_ascii() {
+        return None;
+    }
+    (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).ok()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // Waits for a key with FX0A, adds it and a random number to v1, and loops
+    const ROM: [u8; 8] = [0xF0, 0x0A, 0xC2, 0xFF, 0x81, 0x04, 0x12, 0x00];
+
+    // Record a session the way a frontend would, with irregular frame times
+    fn record() -> Movie {
+        let mut cpu = CPU::new();
+        cpu.seed_random(99);
+        assert!(cpu.load_rom_from_buffer(&ROM).is_ok());
+        let mut scheduler = Scheduler::new(700);
+        let mut movie = Movie::record(&cpu, &ROM, &scheduler);
+
+        for frame in 0..60u64 {
+            if frame % 10 == 3 {
+                let key = (frame / 10) as usize;
+                assert!(cpu.keypress(key, true).is_ok());
+                movie.record_key(scheduler.instructions_run(), key, true);
+            }
+            if frame % 10 == 6 {
+                let key = (frame / 10) as usize;
+                assert!(cpu.keypress(key, false).is_ok());
+                movie.record_key(scheduler.instructions_run(), key, false);
+            }
+            scheduler.advance(&mut cpu, Duration::from_micros(14_000 + frame * 217)).unwrap();
+        }
+        movie.finish(&cpu, &scheduler);
+        assert_ne!(cpu.v_register[1], 0);
+        movie
+    }
+
+    #[test]
+    fn hash() {
+        assert_eq!(fnv1a(b""), 0xCBF29CE484222325);
+        assert_eq!(fnv1a(b"a"), 0xAF63DC4C8601EC8C);
+    }
+
+    #[test]
+    fn text_round_trip() {
+        let movie = record();
+        let text = movie.to_string();
+        assert!(text.starts_with("chip8-movie 1\n"));
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+        assert_eq!(Movie::parse(&format!("# comment\n\n{}", text)).unwrap(), movie);
+    }
+
+    #[test]
+    fn replays_exactly() {
+        let movie = Movie::parse(&record().to_string()).unwrap();
+        assert!(movie.replay(&ROM).is_ok());
+
+        // Dropping a key changes what happens
+        let mut edited = movie.clone();
+        edited.keys.remove(1);
+        assert!(matches!(edited.replay(&ROM), Err(CoreError::DesyncError { .. })));
+    }
+
+    #[test]
+    fn rejects_other_rom() {
+        let movie = record();
+        let mut rom = ROM;
+        rom[3] = 0x0F;
+        assert!(matches!(movie.replay(&rom), Err(CoreError::RomHashError { .. })));
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        let text = record().to_string();
+        assert!(Movie::parse("hello").is_err());
+        assert!(Movie::parse(&text.replace("chip8-movie 1", "chip8-movie 2")).is_err());
+        assert!(Movie::parse(&text.replace("timing instruction", "timing fast")).is_err());
+        assert!(Movie::parse(&text.replace("vf_reset", "megachip")).is_err());
+        assert!(Movie::parse(&text.replace(" down", " sideways")).is_err());
+        let without_speed: String = text.lines().filter(|l| !l.starts_with("speed")).map(|l| format!("{}\n", l)).collect();
+        assert!(Movie::parse(&without_speed).is_err());
+    }
+}
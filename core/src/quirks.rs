@@ -0,0 +1,173 @@
+// Behaviour toggles for instructions that differ between CHIP-8 interpreters.
+// Each field can be set independently, or a preset can be used as a starting point.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Quirks {
+    // 8XY1, 8XY2 and 8XY3 reset vf to 0
+    pub vf_reset: bool,
+    // 8XY6 and 8XYE shift vx in place instead of shifting vy into vx
+    pub shift_vx: bool,
+    // FX55 and FX65 leave i pointing past the last register copied
+    pub memory_increment: bool,
+    // BNNN jumps to XNN + vx instead of NNN + v0
+    pub jump_vx: bool,
+    // Dxyn clips sprites at the screen edges instead of wrapping them around
+    pub clip_sprites: bool,
+    // Enable the SUPER-CHIP instructions, such as 00FE/00FF to switch between lores and hires
+    pub schip_opcodes: bool,
+    // In lores mode, the scroll instructions move by half as many pixels (SUPER-CHIP 1.0)
+    pub lores_half_scroll: bool,
+    // In hires mode, Dxyn sets vf to the number of sprite rows that collided or were clipped
+    pub count_collision_rows: bool,
+    // Enable the XO-CHIP extensions, such as 16 RPL user flags instead of 8
+    pub xo_chip_opcodes: bool,
+    // Dxyn waits for the next 60 Hz tick before the next instruction runs, like the VIP waiting for vblank
+    pub display_wait: bool,
+}
+
+impl Quirks {
+    // Original COSMAC VIP interpreter
+    pub const VIP: Quirks = Quirks {
+        vf_reset: true,
+        shift_vx: false,
+        memory_increment: true,
+        jump_vx: false,
+        clip_sprites: true,
+        schip_opcodes: false,
+        lores_half_scroll: false,
+        count_collision_rows: false,
+        xo_chip_opcodes: false,
+        display_wait: true,
+    };
+
+    // CHIP-48 for the HP-48 calculators
+    pub const CHIP_48: Quirks = Quirks {
+        vf_reset: false,
+        shift_vx: true,
+        memory_increment: true,
+        jump_vx: true,
+        clip_sprites: true,
+        schip_opcodes: false,
+        lores_half_scroll: false,
+        count_collision_rows: false,
+        xo_chip_opcodes: false,
+        display_wait: false,
+    };
+
+    // SUPER-CHIP 1.0
+    pub const SCHIP_1_0: Quirks = Quirks {
+        vf_reset: false,
+        shift_vx: true,
+        memory_increment: true,
+        jump_vx: true,
+        clip_sprites: true,
+        schip_opcodes: true,
+        lores_half_scroll: true,
+        count_collision_rows: true,
+        xo_chip_opcodes: false,
+        display_wait: false,
+    };
+
+    // SUPER-CHIP 1.1
+    pub const SCHIP_1_1: Quirks = Quirks {
+        vf_reset: false,
+        shift_vx: true,
+        memory_increment: false,
+        jump_vx: true,
+        clip_sprites: true,
+        schip_opcodes: true,
+        lores_half_scroll: false,
+        count_collision_rows: true,
+        xo_chip_opcodes: false,
+        display_wait: false,
+    };
+
+    // XO-CHIP, as implemented by Octo
+    pub const XO_CHIP: Quirks = Quirks {
+        vf_reset: false,
+        shift_vx: false,
+        memory_increment: true,
+        jump_vx: false,
+        clip_sprites: false,
+        schip_opcodes: true,
+        lores_half_scroll: false,
+        count_collision_rows: false,
+        xo_chip_opcodes: true,
+        display_wait: false,
+    };
+
+    // Every quirk by its field name, for saving and loading settings as text
+    pub fn fields(&self) -> [(&'static str, bool); 10] {
+        let mut quirks = *self;
+        quirks.fields_mut().map(|(name, value)| (name, *value))
+    }
+
+    // Set a quirk by its field name, returning false if there is no quirk with that name
+    pub fn set_field(&mut self, name: &str, enabled: bool) -> bool {
+        match self.fields_mut().into_iter().find(|(field, _)| *field == name) {
+            Some((_, value)) => {
+                *value = enabled;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn fields_mut(&mut self) -> [(&'static str, &mut bool); 10] {
+        [
+            ("vf_reset", &mut self.vf_reset),
+            ("shift_vx", &mut self.shift_vx),
+            ("memory_increment", &mut self.memory_increment),
+            ("jump_vx", &mut self.jump_vx),
+            ("clip_sprites", &mut self.clip_sprites),
+            ("schip_opcodes", &mut self.schip_opcodes),
+            ("lores_half_scroll", &mut self.lores_half_scroll),
+            ("count_collision_rows", &mut self.count_collision_rows),
+            ("xo_chip_opcodes", &mut self.xo_chip_opcodes),
+            ("display_wait", &mut self.display_wait),
+        ]
+    }
+
+    // Look up a preset by name, e.g. for command line arguments
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name.to_ascii_lowercase().as_str() {
+            "vip" | "chip8" | "chip-8" => Some(Quirks::VIP),
+            "chip48" | "chip-48" => Some(Quirks::CHIP_48),
+            "schip1.0" | "schip10" => Some(Quirks::SCHIP_1_0),
+            "schip1.1" | "schip11" | "schip" => Some(Quirks::SCHIP_1_1),
+            "xochip" | "xo-chip" => Some(Quirks::XO_CHIP),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::VIP
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preset_names() {
+        assert_eq!(Quirks::from_name("VIP"), Some(Quirks::VIP));
+        assert_eq!(Quirks::from_name("chip-48"), Some(Quirks::CHIP_48));
+        assert_eq!(Quirks::from_name("schip1.0"), Some(Quirks::SCHIP_1_0));
+        assert_eq!(Quirks::from_name("schip"), Some(Quirks::SCHIP_1_1));
+        assert_eq!(Quirks::from_name("xo-chip"), Some(Quirks::XO_CHIP));
+        assert_eq!(Quirks::from_name("megachip"), None);
+    }
+
+    #[test]
+    fn fields_by_name() {
+        let mut quirks = Quirks::VIP;
+        for (name, enabled) in Quirks::XO_CHIP.fields() {
+            assert!(quirks.set_field(name, enabled));
+        }
+        assert_eq!(quirks, Quirks::XO_CHIP);
+        assert!(!quirks.set_field("megachip", true));
+    }
+}
@@ -0,0 +1,103 @@
+// Source of the random numbers used by CXNN.
+// The state has to fit in a u64 so it can be stored in save states and restored exactly.
+pub trait RandomSource {
+    fn next_byte(&mut self) -> u8;
+
+    fn state(&self) -> u64;
+
+    fn set_state(&mut self, state: u64);
+}
+
+// xorshift64 can never leave the all-zero state, so a zero seed is swapped for this one
+const ZERO_SEED_REPLACEMENT: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// Small, fast xorshift64 generator that gives the same sequence for the same seed on every platform
+#[derive(Debug, Clone)]
+pub struct Xorshift {
+    state: u64,
+}
+
+impl Xorshift {
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self { state: 0 };
+        rng.set_state(seed);
+        rng
+    }
+}
+
+impl RandomSource for Xorshift {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        (self.state >> 56) as u8
+    }
+
+    fn state(&self) -> u64 {
+        self.state
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.state = if state == 0 { ZERO_SEED_REPLACEMENT } else { state };
+    }
+}
+
+/// Plays back a fixed list of bytes over and over, for tests that need to know exactly what CXNN returns
+#[derive(Debug, Clone)]
+pub struct ScriptedRandom {
+    bytes: Vec<u8>,
+    position: usize,
+}
+
+impl ScriptedRandom {
+    pub fn new(bytes: &[u8]) -> Self {
+        Self { bytes: bytes.to_vec(), position: 0 }
+    }
+}
+
+impl RandomSource for ScriptedRandom {
+    fn next_byte(&mut self) -> u8 {
+        if self.bytes.is_empty() {
+            return 0;
+        }
+        let byte = self.bytes[self.position];
+        self.position = (self.position + 1) % self.bytes.len();
+        byte
+    }
+
+    fn state(&self) -> u64 {
+        self.position as u64
+    }
+
+    fn set_state(&mut self, state: u64) {
+        self.position = if self.bytes.is_empty() { 0 } else { state as usize % self.bytes.len() };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn xorshift_repeats_for_seed() {
+        let mut a = Xorshift::new(1234);
+        let mut b = Xorshift::new(1234);
+        let bytes: Vec<u8> = (0..32).map(|_| a.next_byte()).collect();
+        assert!(bytes.iter().all(|byte| *byte == b.next_byte()));
+        assert!(bytes.iter().any(|byte| *byte != bytes[0]));
+
+        let mut c = Xorshift::new(0);
+        c.set_state(a.state());
+        assert_eq!(c.next_byte(), a.next_byte());
+        assert_ne!(Xorshift::new(0).state(), 0);
+    }
+
+    #[test]
+    fn scripted_bytes() {
+        let mut rng = ScriptedRandom::new(&[1, 2, 3]);
+        assert_eq!([rng.next_byte(), rng.next_byte(), rng.next_byte(), rng.next_byte()], [1, 2, 3, 1]);
+        rng.set_state(2);
+        assert_eq!(rng.next_byte(), 3);
+        assert_eq!(ScriptedRandom::new(&[]).next_byte(), 0);
+    }
+}
@@ -0,0 +1,233 @@
+use std::collections::VecDeque;
+
+use crate::core_error::CoreError;
+use crate::{CPU, TIMER_HZ};
+
+/// 30 seconds of one snapshot per frame
+pub const DEFAULT_REWIND_FRAMES: usize = 30 * TIMER_HZ as usize;
+pub const DEFAULT_REWIND_BYTES: usize = 16 * 1024 * 1024;
+
+// Ring buffer of recent save states for stepping backwards through time.
+// Only the newest snapshot is kept whole. Each older one is stored as a delta that turns the
+// snapshot after it back into it, so stepping back one snapshot is a single delta to undo.
+// A delta is the XOR of the two snapshots, run-length encoded as (unchanged bytes, changed bytes, changed data)
+// runs, which stays small since most of RAM and the display are the same from one frame to the next.
+pub struct Rewind {
+    max_frames: usize,
+    max_bytes: usize,
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    delta_bytes: usize,
+}
+
+impl Rewind {
+    /// Keep at most max_frames snapshots, using at most max_bytes for them
+    pub fn new(max_frames: usize, max_bytes: usize) -> Self {
+        Self {
+            max_frames,
+            max_bytes,
+            latest: None,
+            deltas: VecDeque::new(),
+            delta_bytes: 0,
+        }
+    }
+
+    /// Number of snapshots that can be stepped back to
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deltas.is_empty()
+    }
+
+    /// Bytes currently used by the stored snapshots
+    pub fn memory_used(&self) -> usize {
+        self.latest.as_ref().map_or(0, |latest| latest.len()) + self.delta_bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+        self.delta_bytes = 0;
+    }
+
+    /// Take a snapshot of the CPU, dropping the oldest ones to stay within the limits
+    pub fn push(&mut self, cpu: &CPU) {
+        let state = cpu.save_state();
+        if let Some(previous) = self.latest.take() {
+            let delta = encode_delta(&state, &previous);
+            self.delta_bytes += delta.len();
+            self.deltas.push_back(delta);
+        }
+        self.latest = Some(state);
+
+        while self.deltas.len() > self.max_frames || (self.memory_used() > self.max_bytes && !self.deltas.is_empty()) {
+            if let Some(oldest) = self.deltas.pop_front() {
+                self.delta_bytes -= oldest.len();
+            }
+        }
+    }
+
+    /// Restore the CPU to the snapshot before the newest one, returning false if there is nothing left to rewind
+    pub fn step_back(&mut self, cpu: &mut CPU) -> Result<bool, CoreError> {
+        let (Some(latest), Some(delta)) = (self.latest.as_ref(), self.deltas.pop_back()) else {
+            return Ok(false);
+        };
+        self.delta_bytes -= delta.len();
+
+        let previous = apply_delta(latest, &delta)?;
+        cpu.load_state(&previous)?;
+        self.latest = Some(previous);
+        Ok(true)
+    }
+}
+
+impl Default for Rewind {
+    fn default() -> Self {
+        Self::new(DEFAULT_REWIND_FRAMES, DEFAULT_REWIND_BYTES)
+    }
+}
+
+// Delta that turns from into to: to's length, followed by runs of
+// (bytes to leave alone, bytes to XOR, the XOR values), with lengths as LEB128 varints
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let len = from.len().max(to.len());
+    let xor = |i: usize| from.get(i).copied().unwrap_or(0) ^ to.get(i).copied().unwrap_or(0);
+
+    let mut delta = Vec::new();
+    write_varint(&mut delta, to.len());
+    let mut i = 0;
+    while i < len {
+        let start = i;
+        while i < len && xor(i) == 0 {
+            i += 1;
+        }
+        if i == len {
+            break;
+        }
+        let skip = i - start;
+
+        let start = i;
+        while i < len && xor(i) != 0 {
+            i += 1;
+        }
+        write_varint(&mut delta, skip);
+        write_varint(&mut delta, i - start);
+        delta.extend((start..i).map(xor));
+    }
+    delta
+}
+
+fn apply_delta(from: &[u8], delta: &[u8]) -> Result<Vec<u8>, CoreError> {
+    let mut position = 0;
+    let to_len = read_varint(delta, &mut position)?;
+    let mut to = from.to_vec();
+    to.resize(from.len().max(to_len), 0);
+
+    let mut i = 0;
+    while position < delta.len() {
+        i += read_varint(delta, &mut position)?;
+        let count = read_varint(delta, &mut position)?;
+        if i + count > to.len() || position + count > delta.len() {
+            return Err(corrupt());
+        }
+        for (byte, change) in to[i..i + count].iter_mut().zip(&delta[position..position + count]) {
+            *byte ^= change;
+        }
+        i += count;
+        position += count;
+    }
+
+    to.truncate(to_len);
+    Ok(to)
+}
+
+fn write_varint(buffer: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buffer.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn read_varint(buffer: &[u8], position: &mut usize) -> Result<usize, CoreError> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *buffer.get(*position).ok_or_else(corrupt)?;
+        *position += 1;
+        if shift >= usize::BITS {
+            return Err(corrupt());
+        }
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+fn corrupt() -> CoreError {
+    CoreError::StateError { reason: "corrupt rewind delta".to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::counting_cpu;
+
+    #[test]
+    fn deltas() {
+        let from = [1, 2, 3, 4, 5, 6];
+        let to = [1, 2, 9, 4, 5, 7, 8];
+        let delta = encode_delta(&from, &to);
+        assert_eq!(apply_delta(&from, &delta).unwrap(), to);
+        let delta = encode_delta(&to, &from);
+        assert_eq!(apply_delta(&to, &delta).unwrap(), from);
+        assert_eq!(encode_delta(&from, &from), [6]);
+        assert!(apply_delta(&from, &[6, 0, 9, 1]).is_err());
+    }
+
+    #[test]
+    fn step_back() {
+        let mut cpu = counting_cpu();
+        let mut rewind = Rewind::default();
+        for _ in 0..10 {
+            rewind.push(&cpu);
+            assert!(cpu.cycle().is_ok());
+            assert!(cpu.cycle().is_ok());
+        }
+        rewind.push(&cpu);
+        assert_eq!(rewind.len(), 10);
+        assert_eq!(cpu.v_register[0], 10);
+
+        for count in (0..10).rev() {
+            assert!(rewind.step_back(&mut cpu).unwrap());
+            assert_eq!(cpu.v_register[0], count);
+        }
+        assert!(!rewind.step_back(&mut cpu).unwrap());
+        assert!(rewind.is_empty());
+    }
+
+    #[test]
+    fn limits() {
+        let mut cpu = counting_cpu();
+        let mut rewind = Rewind::new(5, usize::MAX);
+        for _ in 0..20 {
+            rewind.push(&cpu);
+            assert!(cpu.cycle().is_ok());
+        }
+        assert_eq!(rewind.len(), 5);
+
+        // Deltas are much smaller than the snapshots they come from
+        let state_size = cpu.save_state().len();
+        let mut rewind = Rewind::new(usize::MAX, state_size * 2);
+        for _ in 0..200 {
+            rewind.push(&cpu);
+            assert!(cpu.cycle().is_ok());
+        }
+        assert!(rewind.memory_used() <= state_size * 2);
+        assert!(rewind.len() > 50);
+    }
+}
@@ -0,0 +1,414 @@
+use std::time::{Duration, Instant};
+
+use crate::core_error::CoreError;
+use crate::CPU;
+
+pub const TIMER_HZ: u32 = 60;
+const NANOS_PER_SECOND: u128 = 1_000_000_000;
+// After a long stall (window dragged, debugger, etc.), drop the backlog instead of running it all at once
+const MAX_TICKS_PER_UPDATE: u32 = 15;
+
+// Called before every instruction with the number of instructions run so far, e.g. to feed in recorded input
+pub type InstructionHook<'a> = &'a mut dyn FnMut(&mut CPU, u64) -> Result<(), CoreError>;
+
+pub(crate) fn no_hook(_: &mut CPU, _: u64) -> Result<(), CoreError> {
+    Ok(())
+}
+
+// Source of monotonic time, so tests can drive the scheduler with a fake clock
+pub trait Clock {
+    fn now(&self) -> Duration;
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self { start: Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+// Runs the CPU at a steady number of cycles per second, and its timers at exactly 60 Hz,
+// from elapsed wall-clock time and independent of the display refresh rate.
+// A cycle is whatever CPU::cycle() reports: one instruction, or VIP machine cycles with Timing::Vip.
+// Time is counted in units of 1/(60 * cycles_per_second) seconds, so a cycle is 60 units long
+// and a timer tick is cycles_per_second units long, with no rounding drift.
+pub struct Scheduler {
+    cycles_per_second: u32,
+    instruction_limit: Option<u64>,
+    instructions_run: u64,
+    ticks_run: u64,
+    // Leftover time that didn't make up a whole unit, in nanoseconds * units per second
+    remainder: u128,
+    until_instruction: u64,
+    until_tick: u64,
+    last_update: Option<Duration>,
+}
+
+impl Scheduler {
+    pub fn new(cycles_per_second: u32) -> Self {
+        let cycles_per_second = cycles_per_second.max(1);
+        Self {
+            cycles_per_second,
+            instruction_limit: None,
+            instructions_run: 0,
+            ticks_run: 0,
+            remainder: 0,
+            until_instruction: TIMER_HZ as u64,
+            until_tick: cycles_per_second as u64,
+            last_update: None,
+        }
+    }
+
+    pub fn cycles_per_second(&self) -> u32 {
+        self.cycles_per_second
+    }
+
+    /// Change the CPU speed, keeping the timers where they are within the current tick.
+    /// The instruction in progress keeps the cycles it has left, which a unit already scales to the new speed
+    pub fn set_cycles_per_second(&mut self, cycles_per_second: u32) {
+        let cycles_per_second = cycles_per_second.max(1);
+        self.until_tick = (self.until_tick * cycles_per_second as u64) / self.cycles_per_second as u64;
+        self.until_tick = self.until_tick.max(1);
+        self.remainder = 0;
+        self.cycles_per_second = cycles_per_second;
+    }
+
+    /// Stop running instructions after this many, while the timers keep ticking
+    pub fn set_instruction_limit(&mut self, limit: Option<u64>) {
+        self.instruction_limit = limit;
+    }
+
+    pub fn instructions_run(&self) -> u64 {
+        self.instructions_run
+    }
+
+    pub fn ticks_run(&self) -> u64 {
+        self.ticks_run
+    }
+
+    /// Forget the time since the last update, so nothing runs to catch up after a pause
+    pub fn pause(&mut self) {
+        self.last_update = None;
+    }
+
+    /// Read the clock and run everything that should have happened since the last call
+    /// Returns the number of 60 Hz ticks that were run
+    pub fn update(&mut self, cpu: &mut CPU, clock: &dyn Clock) -> Result<u32, CoreError> {
+        self.update_with(cpu, clock, &mut no_hook)
+    }
+
+    pub fn update_with(&mut self, cpu: &mut CPU, clock: &dyn Clock, before_instruction: InstructionHook) -> Result<u32, CoreError> {
+        let now = clock.now();
+        let elapsed = match self.last_update {
+            Some(last) => now.saturating_sub(last),
+            None => Duration::ZERO,
+        };
+        self.last_update = Some(now);
+        self.advance_with(cpu, elapsed, before_instruction)
+    }
+
+    /// Run everything that fits in the elapsed time, carrying any fraction over to the next call
+    pub fn advance(&mut self, cpu: &mut CPU, elapsed: Duration) -> Result<u32, CoreError> {
+        self.advance_with(cpu, elapsed, &mut no_hook)
+    }
+
+    // How the time is split between calls makes no difference to the order things run in,
+    // so the same speed and input always give the same results
+    pub fn advance_with(&mut self, cpu: &mut CPU, elapsed: Duration, before_instruction: InstructionHook) -> Result<u32, CoreError> {
+        let units_per_second = TIMER_HZ as u128 * self.cycles_per_second as u128;
+        self.remainder += elapsed.as_nanos() * units_per_second;
+        let mut budget = self.remainder / NANOS_PER_SECOND;
+        self.remainder %= NANOS_PER_SECOND;
+
+        let max_budget = MAX_TICKS_PER_UPDATE as u128 * self.cycles_per_second as u128;
+        if budget > max_budget {
+            budget = max_budget;
+            self.remainder = 0;
+        }
+        let mut budget = budget as u64;
+
+        let mut ticks = 0;
+        loop {
+            let step = self.until_instruction.min(self.until_tick);
+            if budget < step {
+                self.until_instruction -= budget;
+                self.until_tick -= budget;
+                break;
+            }
+            budget -= step;
+            if self.step(cpu, before_instruction)? {
+                ticks += 1;
+            }
+        }
+
+        Ok(ticks)
+    }
+
+    /// Skip ahead to the next instruction or timer tick and run it, ignoring the clock
+    /// Returns true if the timers ticked
+    pub fn step(&mut self, cpu: &mut CPU, before_instruction: InstructionHook) -> Result<bool, CoreError> {
+        // Step from event to event, running instructions and timer ticks in the order they fall.
+        // The next instruction waits for as many cycles as the last one took.
+        let step = self.until_instruction.min(self.until_tick);
+        self.until_instruction -= step;
+        self.until_tick -= step;
+
+        if self.until_instruction == 0 {
+            if cpu.waiting_for_vblank() {
+                // Nothing runs during a display wait stall, so pick up again straight after the next tick
+                self.until_instruction = self.until_tick;
+            } else {
+                let cycles = self.run_instruction(cpu, before_instruction)?;
+                self.until_instruction = TIMER_HZ as u64 * cycles as u64;
+            }
+        }
+
+        if self.until_tick == 0 {
+            self.until_tick = self.cycles_per_second as u64;
+            cpu.tick_timers();
+            self.ticks_run += 1;
+            return Ok(true);
+        }
+        Ok(false)
+    }
+
+    fn run_instruction(&mut self, cpu: &mut CPU, before_instruction: InstructionHook) -> Result<u32, CoreError> {
+        // Once the limit is reached, let time pass one cycle at a time without running anything
+        if self.instruction_limit.is_some_and(|limit| self.instructions_run >= limit) {
+            return Ok(1);
+        }
+        before_instruction(cpu, self.instructions_run)?;
+        let cycles = cpu.cycle()?;
+        self.instructions_run += 1;
+        Ok(cycles.max(1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::counting_cpu;
+    use std::cell::Cell;
+
+    struct MockClock {
+        now: Cell<Duration>,
+    }
+
+    impl MockClock {
+        fn new() -> Self {
+            Self { now: Cell::new(Duration::ZERO) }
+        }
+
+        fn advance(&self, by: Duration) {
+            self.now.set(self.now.get() + by);
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Duration {
+            self.now.get()
+        }
+    }
+
+    #[test]
+    fn exact_ticks_regardless_of_refresh_rate() {
+        // 144 Hz display for one second
+        let clock = MockClock::new();
+        let mut cpu = counting_cpu();
+        let mut scheduler = Scheduler::new(600);
+        let mut ticks = scheduler.update(&mut cpu, &clock).unwrap();
+        for _ in 0..144 {
+            clock.advance(Duration::from_nanos(NANOS_PER_SECOND as u64 / 144));
+            ticks += scheduler.update(&mut cpu, &clock).unwrap();
+        }
+        assert_eq!(ticks, 59); // 144 frames of 6944444ns fall just short of a full second
+        assert_eq!(scheduler.instructions_run(), 599);
+        clock.advance(Duration::from_millis(1));
+        ticks += scheduler.update(&mut cpu, &clock).unwrap();
+        assert_eq!(ticks, 60);
+        assert_eq!(scheduler.instructions_run(), 600);
+    }
+
+    #[test]
+    fn steady_instruction_rate() {
+        // Same number of instructions whether drawing or not, at any frame rate
+        let mut cpu = CPU::new();
+        assert!(cpu.load_rom_from_buffer(&[0x00, 0xE0, 0x12, 0x00]).is_ok());
+        let mut scheduler = Scheduler::new(700);
+        for _ in 0..120 {
+            scheduler.advance(&mut cpu, Duration::from_nanos(NANOS_PER_SECOND as u64 / 120)).unwrap();
+        }
+        scheduler.advance(&mut cpu, Duration::from_millis(1)).unwrap();
+        assert_eq!(scheduler.instructions_run(), 700);
+    }
+
+    #[test]
+    fn carries_fractional_time() {
+        let mut cpu = counting_cpu();
+        let mut scheduler = Scheduler::new(60);
+        // 10ms is less than a tick, but two of them are more
+        assert_eq!(scheduler.advance(&mut cpu, Duration::from_millis(10)).unwrap(), 0);
+        assert_eq!(scheduler.instructions_run(), 0);
+        assert_eq!(scheduler.advance(&mut cpu, Duration::from_millis(10)).unwrap(), 1);
+        assert_eq!(scheduler.instructions_run(), 1);
+        assert_eq!(scheduler.advance(&mut cpu, Duration::from_millis(14)).unwrap(), 1);
+        assert_eq!(scheduler.advance(&mut cpu, Duration::from_millis(1)).unwrap(), 0);
+    }
+
+    #[test]
+    fn change_speed() {
+        let mut cpu = counting_cpu();
+        let mut scheduler = Scheduler::new(600);
+        assert_eq!(scheduler.advance(&mut cpu, Duration::from_millis(250)).unwrap(), 15);
+        assert_eq!(scheduler.instructions_run(), 150);
+        scheduler.set_cycles_per_second(1200);
+        assert_eq!(scheduler.cycles_per_second(), 1200);
+        assert_eq!(scheduler.advance(&mut cpu, Duration::from_millis(250)).unwrap(), 15);
+        assert_eq!(scheduler.instructions_run(), 450);
+    }
+
+    #[test]
+    fn change_speed_mid_instruction() {
+        // Halfway through a 60 Hz cycle, the other half takes half as long at 120 Hz
+        let mut cpu = counting_cpu();
+        let mut scheduler = Scheduler::new(60);
+        scheduler.advance(&mut cpu, Duration::from_nanos(NANOS_PER_SECOND as u64 / 120)).unwrap();
+        scheduler.set_cycles_per_second(120);
+        scheduler.advance(&mut cpu, Duration::from_millis(4)).unwrap();
+        assert_eq!(scheduler.instructions_run(), 0);
+        scheduler.advance(&mut cpu, Duration::from_millis(1)).unwrap();
+        assert_eq!(scheduler.instructions_run(), 1);
+    }
+
+    #[test]
+    fn ticks_timers() {
+        let mut cpu = counting_cpu();
+        cpu.delay_timer = 30;
+        let mut scheduler = Scheduler::new(60);
+        assert_eq!(scheduler.advance(&mut cpu, Duration::from_millis(250)).unwrap(), 15);
+        assert_eq!(cpu.delay_timer, 15);
+    }
+
+    #[test]
+    fn drops_long_stalls() {
+        let mut cpu = counting_cpu();
+        let mut scheduler = Scheduler::new(60);
+        assert_eq!(scheduler.advance(&mut cpu, Duration::from_secs(10)).unwrap(), MAX_TICKS_PER_UPDATE);
+    }
+
+    #[test]
+    fn instruction_cost() {
+        // At VIP timing, one frame of machine cycles runs a frame's worth of 7001 and 1200
+        let mut cpu = counting_cpu();
+        cpu.set_timing(crate::Timing::Vip);
+        let mut scheduler = Scheduler::new(crate::VIP_CYCLES_PER_FRAME * TIMER_HZ);
+        assert_eq!(scheduler.advance(&mut cpu, Duration::from_millis(250)).unwrap(), 15);
+        let pair = crate::timing::vip_cycles(0x7001, false, 0) + crate::timing::vip_cycles(0x1200, false, 0);
+        let expected = (crate::VIP_CYCLES_PER_FRAME * 15) as u64 * 2 / pair as u64;
+        assert!(scheduler.instructions_run().abs_diff(expected) <= 1);
+    }
+
+    #[test]
+    fn same_order_however_time_is_split() {
+        let mut whole = counting_cpu();
+        let mut whole_scheduler = Scheduler::new(700);
+        whole_scheduler.advance(&mut whole, Duration::from_millis(200)).unwrap();
+
+        let mut split = counting_cpu();
+        let mut split_scheduler = Scheduler::new(700);
+        for _ in 0..200 {
+            split_scheduler.advance(&mut split, Duration::from_millis(1)).unwrap();
+        }
+
+        let mut stepped = counting_cpu();
+        let mut stepped_scheduler = Scheduler::new(700);
+        while stepped_scheduler.instructions_run() < whole_scheduler.instructions_run() {
+            stepped_scheduler.step(&mut stepped, &mut no_hook).unwrap();
+        }
+
+        assert_eq!(whole_scheduler.ticks_run(), 12);
+        assert_eq!(split_scheduler.ticks_run(), 12);
+        assert_eq!(stepped_scheduler.ticks_run(), 12);
+        assert_eq!(split.save_state()[..32], whole.save_state()[..32]);
+        assert_eq!(stepped.save_state()[..32], whole.save_state()[..32]);
+    }
+
+    #[test]
+    fn hook_before_instruction() {
+        let mut cpu = counting_cpu();
+        let mut scheduler = Scheduler::new(600);
+        let mut seen = Vec::new();
+        scheduler.advance_with(&mut cpu, Duration::from_millis(10), &mut |_, n| {
+            seen.push(n);
+            Ok(())
+        }).unwrap();
+        assert_eq!(seen, [0, 1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn pause() {
+        let clock = MockClock::new();
+        let mut cpu = counting_cpu();
+        let mut scheduler = Scheduler::new(600);
+        scheduler.update(&mut cpu, &clock).unwrap();
+        clock.advance(Duration::from_millis(100));
+        scheduler.pause();
+        assert_eq!(scheduler.update(&mut cpu, &clock).unwrap(), 0);
+        assert_eq!(scheduler.instructions_run(), 0);
+    }
+
+    #[test]
+    fn instruction_limit() {
+        let mut cpu = counting_cpu();
+        let mut scheduler = Scheduler::new(600);
+        scheduler.set_instruction_limit(Some(25));
+        assert_eq!(scheduler.advance(&mut cpu, Duration::from_millis(100)).unwrap(), 6);
+        assert_eq!(scheduler.instructions_run(), 25);
+    }
+
+    #[test]
+    fn display_wait_stalls_are_not_instructions() {
+        // Add 4 to v0, draw, jump back: three instructions per frame once each draw waits for the tick
+        let mut cpu = CPU::with_quirks(crate::Quirks::VIP);
+        assert!(cpu.load_rom_from_buffer(&[0x70, 0x04, 0xD0, 0x11, 0x12, 0x00]).is_ok());
+        let mut scheduler = Scheduler::new(600);
+        scheduler.set_instruction_limit(Some(90));
+        for _ in 0..60 {
+            scheduler.advance(&mut cpu, Duration::from_nanos(NANOS_PER_SECOND as u64 / 60)).unwrap();
+        }
+        assert_eq!(scheduler.instructions_run(), 90);
+        assert_eq!(cpu.v_register(0), 30 * 4);
+    }
+
+    #[test]
+    fn display_wait_skips_to_tick() {
+        // Under VIP timing a stall is thousands of cycles, but it's a single step to the tick
+        let mut cpu = CPU::with_quirks(crate::Quirks::VIP);
+        cpu.set_timing(crate::Timing::Vip);
+        assert!(cpu.load_rom_from_buffer(&[0xD0, 0x11, 0x12, 0x00]).is_ok());
+        let mut scheduler = Scheduler::new(crate::VIP_CYCLES_PER_FRAME * TIMER_HZ);
+        let mut steps = 0;
+        while scheduler.ticks_run() < 10 {
+            scheduler.step(&mut cpu, &mut no_hook).unwrap();
+            steps += 1;
+        }
+        // A jump and a draw every frame, apart from the first which starts with the draw
+        assert_eq!(scheduler.instructions_run(), 19);
+        assert!(steps <= 4 * 10);
+    }
+}
@@ -0,0 +1,286 @@
+use crate::core_error::CoreError;
+use crate::{CPU, AUDIO_PATTERN_BYTES, NUM_COLORS, NUM_KEYS, NUM_REGISTERS, STACK_SIZE, XO_NUM_RPL_FLAGS};
+
+// Save state layout, all multi-byte values big-endian:
+//   magic "C8ST", version u8
+//   pc u16, i u16, v registers [u8; 16], stack depth u8 + [u16; depth]
+//   delay timer u8, sound timer u8
+//   keys u16 bitmask, pressed key u8, wait flags u8 (bit 0 FX0A waiting for press, bit 1 waiting for vblank)
+//   selected planes u8, audio pattern [u8; 16], pitch u8, rpl flags [u8; 16]
+//   hires u8, display pixels [u8; width * height]
+//   ram length u32, ram [u8; length]
+//   random source state u64 (version 2 onwards)
+// Bump STATE_VERSION whenever the layout changes, and keep reading the older versions where possible
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+const STATE_VERSION: u8 = 2;
+
+const WAIT_FOR_PRESS: u8 = 0x01;
+const WAIT_FOR_VBLANK: u8 = 0x02;
+
+impl CPU {
+    /// Snapshot everything needed to resume the running program later, as a versioned binary blob
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(self.ram.len() + self.display.pixels().len() + 128);
+        state.extend_from_slice(STATE_MAGIC);
+        state.push(STATE_VERSION);
+
+        state.extend_from_slice(&self.program_counter.to_be_bytes());
+        state.extend_from_slice(&self.index_register.to_be_bytes());
+        state.extend_from_slice(&self.v_register);
+        state.push(self.stack.len() as u8);
+        for address in self.stack.iter() {
+            state.extend_from_slice(&address.to_be_bytes());
+        }
+        state.push(self.delay_timer);
+        state.push(self.sound_timer);
+
+        let keys = self.key_states.iter().enumerate()
+            .fold(0u16, |keys, (i, pressed)| keys | ((*pressed as u16) << i));
+        state.extend_from_slice(&keys.to_be_bytes());
+        state.push(self.pressed_key);
+        let mut wait = 0;
+        if self.wait_for_press {
+            wait |= WAIT_FOR_PRESS;
+        }
+        if self.wait_for_vblank {
+            wait |= WAIT_FOR_VBLANK;
+        }
+        state.push(wait);
+
+        state.push(self.selected_planes);
+        state.extend_from_slice(&self.audio_pattern);
+        state.push(self.pitch);
+        state.extend_from_slice(&self.rpl_flags);
+
+        state.push(self.display.is_hires() as u8);
+        state.extend_from_slice(self.display.pixels());
+
+        state.extend_from_slice(&(self.ram.len() as u32).to_be_bytes());
+        state.extend_from_slice(&self.ram);
+
+        state.extend_from_slice(&self.rng.state().to_be_bytes());
+        state
+    }
+
+    /// Restore a snapshot from save_state(). The CPU is left untouched if the state can't be read.
+    /// Quirks and timing aren't part of the state, so the CPU must use the same memory size as when it was saved.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), CoreError> {
+        let mut reader = StateReader { state, position: 0 };
+        if reader.bytes(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(CoreError::StateError { reason: "not a save state".to_string() });
+        }
+        let version = reader.u8()?;
+        if version == 0 || version > STATE_VERSION {
+            return Err(CoreError::StateError { reason: format!("unsupported version {}", version) });
+        }
+
+        // Read into a copy so a truncated or mismatched state can't leave the CPU half loaded
+        let mut cpu = CPU::with_quirks(self.quirks);
+
+        cpu.program_counter = reader.u16()?;
+        cpu.index_register = reader.u16()?;
+        cpu.v_register.copy_from_slice(reader.bytes(NUM_REGISTERS)?);
+        let depth = reader.u8()? as usize;
+        if depth > STACK_SIZE {
+            return Err(CoreError::StateError { reason: format!("stack depth {} too large", depth) });
+        }
+        for _ in 0..depth {
+            cpu.stack.push(reader.u16()?);
+        }
+        cpu.delay_timer = reader.u8()?;
+        cpu.sound_timer = reader.u8()?;
+
+        let keys = reader.u16()?;
+        for (i, pressed) in cpu.key_states.iter_mut().enumerate() {
+            *pressed = keys & (1 << i) != 0;
+        }
+        cpu.pressed_key = reader.u8()?;
+        if cpu.pressed_key as usize >= NUM_KEYS {
+            return Err(CoreError::StateError { reason: format!("invalid key {}", cpu.pressed_key) });
+        }
+        let wait = reader.u8()?;
+        cpu.wait_for_press = wait & WAIT_FOR_PRESS != 0;
+        cpu.wait_for_vblank = wait & WAIT_FOR_VBLANK != 0;
+
+        cpu.selected_planes = reader.u8()?;
+        if cpu.selected_planes as usize >= NUM_COLORS {
+            return Err(CoreError::StateError { reason: format!("invalid plane selection {}", cpu.selected_planes) });
+        }
+        cpu.audio_pattern.copy_from_slice(reader.bytes(AUDIO_PATTERN_BYTES)?);
+        cpu.pitch = reader.u8()?;
+        cpu.rpl_flags.copy_from_slice(reader.bytes(XO_NUM_RPL_FLAGS)?);
+
+        cpu.display.set_hires(reader.u8()? != 0);
+        let pixels = cpu.display.pixels().len();
+        cpu.display.buffer.copy_from_slice(reader.bytes(pixels)?);
+        if let Some(pixel) = cpu.display.pixels().iter().find(|pixel| **pixel as usize >= NUM_COLORS) {
+            return Err(CoreError::StateError { reason: format!("invalid pixel {}", pixel) });
+        }
+
+        let ram_size = reader.u32()? as usize;
+        if ram_size != cpu.ram.len() {
+            return Err(CoreError::StateError {
+                reason: format!("saved with {} bytes of memory, but this CPU has {}", ram_size, cpu.ram.len()),
+            });
+        }
+        cpu.ram.copy_from_slice(reader.bytes(ram_size)?);
+
+        // Version 1 states didn't store the random source, so it carries on from where it is
+        let rng_state = match version {
+            1 => None,
+            _ => Some(reader.u64()?),
+        };
+
+        if reader.position != state.len() {
+            return Err(CoreError::StateError { reason: "unexpected data after the end".to_string() });
+        }
+
+        // Everything but the frontend's configuration comes from the state
+        cpu.timing = self.timing;
+        cpu.flag_storage = self.flag_storage.take();
+        std::mem::swap(&mut cpu.rng, &mut self.rng);
+        if let Some(rng_state) = rng_state {
+            cpu.rng.set_state(rng_state);
+        }
+        cpu.display_update_flag = true;
+        *self = cpu;
+        Ok(())
+    }
+}
+
+struct StateReader<'a> {
+    state: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], CoreError> {
+        let end = self.position + len;
+        if end > self.state.len() {
+            return Err(CoreError::StateError { reason: "truncated".to_string() });
+        }
+        let bytes = &self.state[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, CoreError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, CoreError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, CoreError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, CoreError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.bytes(8)?);
+        Ok(u64::from_be_bytes(bytes))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Quirks, START_ADDRESS};
+
+    // Run a few instructions so most of the state is away from its defaults
+    fn busy_cpu() -> CPU {
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        // 00FF hires, 6005 v0 = 5, A050 i = font, 2208 call, D015 draw, F015 delay = 5
+        let rom = [0x00, 0xFF, 0x60, 0x05, 0xA0, 0x50, 0x22, 0x08, 0xD0, 0x15, 0xF0, 0x15, 0x12, 0x0C];
+        assert!(cpu.load_rom_from_buffer(&rom).is_ok());
+        for _ in 0..6 {
+            assert!(cpu.cycle().is_ok());
+        }
+        assert!(cpu.keypress(3, true).is_ok());
+        cpu
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut cpu = busy_cpu();
+        let state = cpu.save_state();
+
+        let mut restored = CPU::with_quirks(Quirks::XO_CHIP);
+        assert!(restored.load_state(&state).is_ok());
+        assert_eq!(restored.save_state(), state);
+        assert_eq!(restored.program_counter, START_ADDRESS + 12);
+        assert_eq!(restored.stack, vec![START_ADDRESS + 8]);
+        assert!(restored.display.is_hires());
+        assert_eq!(restored.display.pixels(), cpu.display.pixels());
+        assert!(restored.key_states[3]);
+
+        // Both CPUs carry on the same way
+        assert!(cpu.cycle().is_ok());
+        assert!(restored.cycle().is_ok());
+        assert_eq!(restored.save_state(), cpu.save_state());
+    }
+
+    #[test]
+    fn restores_random_source() {
+        let mut cpu = busy_cpu();
+        cpu.seed_random(7);
+        assert!(cpu.execute(0xC0FF).is_ok());
+        let state = cpu.save_state();
+        assert!(cpu.execute(0xC0FF).is_ok());
+        let expected = cpu.v_register[0];
+
+        let mut restored = CPU::with_quirks(Quirks::XO_CHIP);
+        assert!(restored.load_state(&state).is_ok());
+        assert!(restored.execute(0xC0FF).is_ok());
+        assert_eq!(restored.v_register[0], expected);
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let mut cpu = busy_cpu();
+        cpu.seed_random(7);
+        let mut state = cpu.save_state();
+        state.truncate(state.len() - 8);
+        state[4] = 1;
+
+        let mut restored = CPU::with_quirks(Quirks::XO_CHIP);
+        assert!(restored.load_state(&state).is_ok());
+        assert_eq!(restored.program_counter, cpu.program_counter);
+        assert_eq!(restored.ram, cpu.ram);
+    }
+
+    #[test]
+    fn rejects_bad_states() {
+        let state = busy_cpu().save_state();
+        let mut cpu = CPU::with_quirks(Quirks::XO_CHIP);
+        cpu.seed_random(1);
+        let before = cpu.save_state();
+
+        assert!(cpu.load_state(b"nope").is_err());
+        assert!(cpu.load_state(&state[..state.len() - 1]).is_err());
+
+        let mut newer = state.clone();
+        newer[4] = STATE_VERSION + 1;
+        assert!(matches!(cpu.load_state(&newer), Err(CoreError::StateError { .. })));
+
+        // 4K VIP memory can't hold a 64K XO-CHIP state
+        assert!(CPU::new().load_state(&state).is_err());
+
+        // Planes and pixels index the palette, so they have to be real colors
+        // Header, pc, i, registers, stack of one, timers, keys, pressed key, wait flags
+        let planes = STATE_MAGIC.len() + 1 + 2 + 2 + NUM_REGISTERS + 1 + 2 + 2 + 2 + 1 + 1;
+        let mut bad_planes = state.clone();
+        bad_planes[planes] = NUM_COLORS as u8;
+        assert!(matches!(cpu.load_state(&bad_planes), Err(CoreError::StateError { .. })));
+        let pixels = planes + 1 + AUDIO_PATTERN_BYTES + 1 + XO_NUM_RPL_FLAGS + 1;
+        let mut bad_pixel = state.clone();
+        bad_pixel[pixels] = 0xFF;
+        assert!(matches!(cpu.load_state(&bad_pixel), Err(CoreError::StateError { .. })));
+
+        // A failed load leaves the CPU as it was
+        assert_eq!(cpu.save_state(), before);
+    }
+}
@@ -0,0 +1,9 @@
+// Fixtures shared by the unit tests of more than one module
+use crate::CPU;
+
+// CPU running an endless loop of 7001 (add 1 to v0) and a jump back
+pub(crate) fn counting_cpu() -> CPU {
+    let mut cpu = CPU::new();
+    assert!(cpu.load_rom_from_buffer(&[0x70, 0x01, 0x12, 0x00]).is_ok());
+    cpu
+}
@@ -0,0 +1,102 @@
+// How much time each instruction takes, as reported by CPU::cycle()
+
+/// Machine cycles in one 60 Hz frame on the COSMAC VIP (1.76 MHz clock, 8 clocks per machine cycle)
+pub const VIP_CYCLES_PER_FRAME: u32 = 3668;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Timing {
+    /// Every instruction takes one cycle
+    #[default]
+    Instruction,
+    /// Every instruction takes as many machine cycles as it did on the COSMAC VIP interpreter
+    Vip,
+}
+
+impl Timing {
+    pub fn from_name(name: &str) -> Option<Timing> {
+        match name.to_ascii_lowercase().as_str() {
+            "instruction" => Some(Timing::Instruction),
+            "vip" => Some(Timing::Vip),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Timing::Instruction => "instruction",
+            Timing::Vip => "vip",
+        }
+    }
+}
+
+// Fetching and decoding an instruction, before the handler for it runs
+const VIP_FETCH_CYCLES: u32 = 40;
+// Extra time when a skip instruction takes the branch
+const VIP_SKIP_CYCLES: u32 = 4;
+
+// VIP machine cycles for an instruction, from Laurence Scotford's disassembly of the interpreter.
+// skipped is whether a skip instruction took the branch, and sprite_x is vx before a Dxyn ran.
+pub(crate) fn vip_cycles(op_code: u16, skipped: bool, sprite_x: u8) -> u32 {
+    let x = (op_code & 0x0F00) >> 8;
+    let n = op_code & 0x000F;
+    let skip = if skipped { VIP_SKIP_CYCLES } else { 0 };
+
+    let execute = match op_code >> 12 {
+        0x0 => match op_code {
+            0x00E0 => 24 + 3078, // Clears all 256 bytes of display memory
+            0x00EE => 10,
+            _ => 10,
+        },
+        0x1 => 12,
+        0x2 => 26,
+        0x3 | 0x4 => 10 + skip,
+        0x5 | 0x9 => 14 + skip,
+        0x6 => 6,
+        0x7 => 10,
+        0x8 => 44,
+        0xA => 12,
+        0xB => 22,
+        0xC => 36,
+        0xD => {
+            // Sprite bytes are shifted into place one bit at a time, and unaligned rows touch two bytes
+            let shift = (sprite_x % 8) as u32;
+            let per_row = if shift == 0 { 34 } else { 46 + (shift * 4) };
+            26 + (n as u32 * per_row)
+        },
+        0xE => 14 + skip,
+        0xF => match op_code & 0x00FF {
+            0x07 => 10,
+            0x0A => 19,
+            0x15 | 0x18 => 10,
+            0x1E => 16,
+            0x29 => 16,
+            0x33 => 84 + 16 * 3,
+            0x55 | 0x65 => 14 + (14 * (x as u32 + 1)),
+            _ => 10,
+        },
+        _ => 10,
+    };
+
+    VIP_FETCH_CYCLES + execute
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixed_costs() {
+        assert_eq!(vip_cycles(0x6A12, false, 0), 46);
+        assert_eq!(vip_cycles(0x8124, false, 0), 84);
+        assert_eq!(vip_cycles(0x00E0, false, 0), 3142);
+    }
+
+    #[test]
+    fn variable_costs() {
+        assert_eq!(vip_cycles(0x3012, true, 0), vip_cycles(0x3012, false, 0) + VIP_SKIP_CYCLES);
+        assert!(vip_cycles(0xF555, false, 0) > vip_cycles(0xF055, false, 0));
+        // More rows and unaligned sprites cost more
+        assert!(vip_cycles(0xD015, false, 0) > vip_cycles(0xD011, false, 0));
+        assert!(vip_cycles(0xD015, false, 3) > vip_cycles(0xD015, false, 8));
+    }
+}
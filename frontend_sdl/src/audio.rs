@@ -0,0 +1,55 @@
+use core::{AudioSource, CPU};
+use sdl2::{
+    audio::{AudioCallback, AudioDevice, AudioSpecDesired}, AudioSubsystem
+};
+
+const SAMPLE_RATE: i32 = 44100;
+
+// Pulls samples from the core's audio source on SDL's audio thread
+pub struct ToneCallback {
+    source: Box<dyn AudioSource + Send>,
+    sample_rate: u32,
+}
+
+impl AudioCallback for ToneCallback {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        self.source.render(out, self.sample_rate);
+    }
+}
+
+pub struct Beeper {
+    device: AudioDevice<ToneCallback>,
+}
+
+impl Beeper {
+    pub fn new(subsystem: &AudioSubsystem, source: Box<dyn AudioSource + Send>) -> Result<Self, String> {
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+
+        let device = subsystem.open_playback(None, &desired, |spec| ToneCallback {
+            source,
+            sample_rate: spec.freq as u32,
+        })?;
+        device.resume();
+
+        Ok(Self { device })
+    }
+
+    // Called once per frame to pass the sound state over to the audio thread
+    pub fn update(&mut self, cpu: &CPU) {
+        self.device.lock().source.update(cpu);
+    }
+
+    // A paused CPU keeps its sound timer where it was, so stop the device instead of buzzing
+    pub fn set_paused(&mut self, paused: bool) {
+        match paused {
+            true => self.device.pause(),
+            false => self.device.resume(),
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use core::{disassemble, Breakpoint, CoreError, DisassemblyLine, InstructionHook, Scheduler, Syntax, CPU};
+use sdl2::{pixels::Color, rect::Rect, render::{BlendMode, Canvas}, video::Window};
+
+use crate::text::{draw_text, CHAR_WIDTH, LINE_HEIGHT};
+
+// Stepping over or out of a long subroutine runs one timer tick per frame, close to normal speed
+const RUN_TICKS_PER_FRAME: u64 = 1;
+const BACKGROUND: Color = Color::RGBA(0, 0, 0, 200);
+const TEXT_COLOR: Color = Color::RGB(255, 255, 255);
+const CURRENT_COLOR: Color = Color::RGB(255, 255, 0);
+const DISASSEMBLY_COLUMN: i32 = 36;
+const DISASSEMBLY_LINES: usize = 25;
+// Lines of disassembly shown before the program counter
+const DISASSEMBLY_CONTEXT: usize = 8;
+const HELP: &str = "SPACE PAUSE/RUN  N STEP  O STEP OVER  U RETURN  F10 CLOSE";
+
+// Pauses the CPU and steps through it, with an overlay showing the registers and the code around PC
+pub struct Debugger {
+    visible: bool,
+    paused: bool,
+    breakpoint: Option<Breakpoint>,
+}
+
+impl Debugger {
+    /// Start with the overlay open and the CPU paused before its first instruction, or hidden
+    pub fn new(open: bool) -> Self {
+        Self { visible: open, paused: open, breakpoint: None }
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    /// Opening the debugger pauses the CPU, closing it lets it run again
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+        self.paused = self.visible;
+        self.breakpoint = None;
+    }
+
+    /// Also stops a step over or run to return that hasn't finished yet
+    pub fn pause_or_resume(&mut self) {
+        self.paused = !self.paused || self.breakpoint.is_some();
+        self.breakpoint = None;
+    }
+
+    pub fn step(&mut self) {
+        self.run_to(Breakpoint::Step);
+    }
+
+    pub fn step_over(&mut self, cpu: &CPU) {
+        self.run_to(Breakpoint::step_over(cpu));
+    }
+
+    pub fn run_to_return(&mut self, cpu: &CPU) {
+        match Breakpoint::run_to_return(cpu) {
+            Some(breakpoint) => self.run_to(breakpoint),
+            None => eprintln!("Not in a subroutine"),
+        }
+    }
+
+    fn run_to(&mut self, breakpoint: Breakpoint) {
+        self.paused = true;
+        self.breakpoint = Some(breakpoint);
+    }
+
+    /// Paused with nothing left to run
+    pub fn halted(&self) -> bool {
+        self.paused && self.breakpoint.is_none()
+    }
+
+    /// Running towards a breakpoint, one frame at a time
+    pub fn stepping(&self) -> bool {
+        self.breakpoint.is_some()
+    }
+
+    /// Run towards the breakpoint for one frame, returning the number of timer ticks that ran
+    pub fn update(&mut self, scheduler: &mut Scheduler, cpu: &mut CPU, before_instruction: InstructionHook) -> Result<u32, CoreError> {
+        let Some(breakpoint) = self.breakpoint else {
+            return Ok(0);
+        };
+        let ticks = scheduler.ticks_run();
+        if breakpoint.run(scheduler, cpu, before_instruction, RUN_TICKS_PER_FRAME)? {
+            self.breakpoint = None;
+        }
+        // Stepping ignores the clock, so don't catch up on the time it took once running again
+        scheduler.pause();
+        Ok((scheduler.ticks_run() - ticks) as u32)
+    }
+
+    pub fn draw(&self, cpu: &CPU, canvas: &mut Canvas<Window>) -> Result<(), String> {
+        let (width, height) = canvas.output_size()?;
+        canvas.set_blend_mode(BlendMode::Blend);
+        canvas.set_draw_color(BACKGROUND);
+        canvas.fill_rect(Rect::new(0, 0, width, height))?;
+
+        let status = match (self.paused, self.breakpoint) {
+            (_, Some(Breakpoint::Step)) => "STEPPING",
+            (_, Some(Breakpoint::StepOver { .. })) => "STEPPING OVER",
+            (_, Some(Breakpoint::Return { .. })) => "RUNNING TO RETURN",
+            (true, None) => "PAUSED",
+            (false, None) => "RUNNING",
+        };
+
+        let mut lines = vec![
+            status.to_string(),
+            String::new(),
+            format!("PC {:04X}  I {:04X}", cpu.program_counter(), cpu.index_register()),
+            format!("DT {:02X}    ST {:02X}", cpu.delay_timer(), cpu.sound_timer()),
+            String::new(),
+        ];
+        for (row, values) in cpu.v_registers().chunks(4).enumerate() {
+            let registers: Vec<String> = values.iter().enumerate()
+                .map(|(i, value)| format!("V{:X} {:02X}", row * 4 + i, value))
+                .collect();
+            lines.push(registers.join("  "));
+        }
+        lines.push(String::new());
+        lines.push(format!("STACK {}", cpu.stack().len()));
+        for addresses in cpu.stack().chunks(4) {
+            let addresses: Vec<String> = addresses.iter().map(|address| format!("{:04X}", address)).collect();
+            lines.push(addresses.join(" "));
+        }
+
+        for (i, line) in lines.iter().enumerate() {
+            draw_text(canvas, CHAR_WIDTH as i32, (LINE_HEIGHT * i as u32) as i32, line, TEXT_COLOR)?;
+        }
+        draw_text(canvas, CHAR_WIDTH as i32, (height - LINE_HEIGHT) as i32, HELP, TEXT_COLOR)?;
+
+        let pc = cpu.program_counter();
+        for (i, line) in disassembly(cpu).iter().enumerate() {
+            let current = line.address == pc;
+            let text = format!("{}{:04X}  {}", if current { "> " } else { "  " }, line.address, line.text);
+            let color = if current { CURRENT_COLOR } else { TEXT_COLOR };
+            draw_text(canvas, DISASSEMBLY_COLUMN * CHAR_WIDTH as i32, (LINE_HEIGHT * i as u32) as i32, &text, color)?;
+        }
+
+        Ok(())
+    }
+}
+
+// Instructions around the program counter. Data can throw the alignment off, in which case the
+// listing starts at the program counter instead
+fn disassembly(cpu: &CPU) -> Vec<DisassemblyLine> {
+    let memory = cpu.memory();
+    let pc = (cpu.program_counter() as usize).min(memory.len());
+    let list = |start: usize| {
+        let end = (pc + 2 * DISASSEMBLY_LINES).min(memory.len());
+        disassemble(&memory[start..end], start as u16, Syntax::Classic)
+    };
+
+    let mut lines = list(pc.saturating_sub(2 * DISASSEMBLY_CONTEXT));
+    let current = match lines.iter().position(|line| line.address as usize == pc) {
+        Some(current) => current,
+        None => {
+            lines = list(pc);
+            0
+        }
+    };
+    lines.into_iter().skip(current.saturating_sub(DISASSEMBLY_CONTEXT)).take(DISASSEMBLY_LINES).collect()
+}
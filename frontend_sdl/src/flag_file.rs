@@ -0,0 +1,27 @@
+use core::FlagStorage;
+use std::{fs, io, path::{Path, PathBuf}};
+
+// Saves the RPL user flags to a file next to the ROM, e.g. game.ch8 -> game.flags
+pub struct FlagFile {
+    path: PathBuf,
+}
+
+impl FlagFile {
+    pub fn for_rom(rom_path: &Path) -> Self {
+        Self { path: rom_path.with_extension("flags") }
+    }
+}
+
+impl FlagStorage for FlagFile {
+    fn load(&mut self) -> io::Result<Option<Vec<u8>>> {
+        match fs::read(&self.path) {
+            Ok(flags) => Ok(Some(flags)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn save(&mut self, flags: &[u8]) -> io::Result<()> {
+        fs::write(&self.path, flags)
+    }
+}
@@ -1,55 +1,172 @@
+mod audio;
+mod debugger;
+mod flag_file;
+mod palette;
+mod state_file;
+mod text;
+
 use core::*;
-use std::error;
+use audio::Beeper;
+use debugger::Debugger;
+use flag_file::FlagFile;
+use state_file::StateSlots;
+use std::{error, fs, path::{Path, PathBuf}};
 use sdl2::{
-    event::Event, keyboard::Keycode, pixels::Color, rect::Rect, render::Canvas, video::Window
+    event::Event, keyboard::Keycode, rect::Rect, render::Canvas, video::Window
 };
 
+pub use palette::{Palette, DEFAULT_PALETTE, parse_palette};
+
 const DISPLAY_SCALE: u32 = 10;
 const WINDOW_WIDTH: u32 = (SCREEN_WIDTH as u32) * DISPLAY_SCALE;
 const WINDOW_HEIGHT: u32 = (SCREEN_HEIGHT as u32) * DISPLAY_SCALE;
+const WINDOW_TITLE: &str = "Chip-8 Emulator";
+// The speed hotkeys change the speed by a percentage, so they work the same in instructions and VIP machine cycles
+const SPEED_STEP_PERCENT: u32 = 10;
+const MIN_SPEED: u32 = 100;
+const MAX_SPEED: u32 = 100_000;
+// A tenth to a hundred times the original VIP's speed
+const MIN_VIP_SPEED: u32 = VIP_CYCLES_PER_FRAME * TIMER_HZ / 10;
+const MAX_VIP_SPEED: u32 = VIP_CYCLES_PER_FRAME * TIMER_HZ * 100;
+const MOVIE_LOCKED: &str = "Not available while recording or playing a movie";
 
 pub struct GameSDL {
     cpu: CPU,
     context: sdl2::Sdl,
-    subsystem: sdl2::VideoSubsystem,
-    window: sdl2::video::Window,
     canvas: sdl2::render::Canvas<Window>,
-    ticks_per_frame: u32,
+    beeper: Option<Beeper>,
+    state_slots: StateSlots,
+    rewind: Rewind,
+    rom: Vec<u8>,
+    record_path: Option<PathBuf>,
+    player: Option<MoviePlayer>,
+    instructions_per_second: u32,
     run_cycles: u32,
+    palette: Palette,
+    debugger: Debugger,
+}
+
+pub struct Options {
+    pub quirks: Quirks,
+    pub timing: Timing,
+    pub instructions_per_second: u32,
+    pub run_cycles: u32,
+    pub palette: Palette,
+    pub pitch: f32,
+    pub volume: f32,
+    pub rewind_bytes: usize,
+    pub seed: Option<u64>,
+    pub record_path: Option<PathBuf>,
+    pub playback: Option<Movie>,
+    pub debug: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            quirks: Quirks::default(),
+            timing: Timing::default(),
+            instructions_per_second: 700,
+            run_cycles: 0,
+            palette: DEFAULT_PALETTE,
+            pitch: DEFAULT_PITCH_HZ,
+            volume: DEFAULT_VOLUME,
+            rewind_bytes: DEFAULT_REWIND_BYTES,
+            seed: None,
+            record_path: None,
+            playback: None,
+            debug: false,
+        }
+    }
 }
 
-pub fn init_frontend(rom_buffer: Vec<u8>, ticks_per_frame: u32, run_cycles: u32) -> Result<GameSDL, Box<dyn error::Error>> {
+pub fn init_frontend(rom_path: &Path, rom_buffer: Vec<u8>, options: &Options) -> Result<GameSDL, Box<dyn error::Error>> {
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
 
     // Create window
     let window = video_subsystem
-        .window("Chip-8 Emulator", WINDOW_WIDTH, WINDOW_HEIGHT)
+        .window(WINDOW_TITLE, WINDOW_WIDTH, WINDOW_HEIGHT)
         .position_centered()
         .opengl()
         .build()?;
 
-    // Create canvas in window
-    let canvas = window.clone().into_canvas().present_vsync().build()?;
+    // Create canvas in window, which keeps the window and video subsystem alive
+    let canvas = window.into_canvas().present_vsync().build()?;
 
     let mut game = GameSDL {
-        cpu: CPU::new(),
+        cpu: CPU::with_quirks(options.quirks),
         context: sdl_context,
-        subsystem: video_subsystem,
-        window: window,
-        canvas: canvas,
-        ticks_per_frame: ticks_per_frame,
-        run_cycles: run_cycles,
+        canvas,
+        beeper: None,
+        state_slots: StateSlots::for_rom(rom_path),
+        rewind: Rewind::new(DEFAULT_REWIND_FRAMES, options.rewind_bytes),
+        rom: Vec::new(),
+        record_path: options.record_path.clone(),
+        player: None,
+        instructions_per_second: options.instructions_per_second,
+        run_cycles: options.run_cycles,
+        palette: options.palette,
+        debugger: Debugger::new(options.debug),
+    };
+
+    // A movie brings its own settings and flags, and fails here if it was recorded with a different ROM
+    if let Some(movie) = &options.playback {
+        let (cpu, _) = movie.start(&rom_buffer)?;
+        game.cpu = cpu;
+        game.player = Some(MoviePlayer::new(movie.clone()));
+    } else {
+        game.cpu.set_timing(options.timing);
+        if let Some(seed) = options.seed {
+            game.cpu.seed_random(seed);
+        }
+        game.cpu.load_rom_from_buffer(&rom_buffer)?;
+        game.cpu.set_flag_storage(Box::new(FlagFile::for_rom(rom_path)))?;
+    }
+    game.rom = rom_buffer;
+
+    // XO-CHIP plays the ROM's own audio pattern, starting from the CPU's pitch, everything else gets a plain beep
+    let source: Box<dyn AudioSource + Send> = match game.cpu.quirks().xo_chip_opcodes {
+        true => Box::new(PatternPlayer::for_cpu(&game.cpu, options.volume)),
+        false => Box::new(SquareWave::new(options.pitch, options.volume)),
+    };
+
+    // Audio is optional, keep running silently if there is no audio device
+    let beeper = game.context.audio()
+        .and_then(|audio| Beeper::new(&audio, source));
+    game.beeper = match beeper {
+        Ok(beeper) => Some(beeper),
+        Err(e) => {
+            eprintln!("Audio disabled: {}", e);
+            None
+        }
     };
 
-    game.cpu.load_rom_from_buffer(&rom_buffer)?;
-    
     Ok(game)
 }
 
 pub fn run_game(game: &mut GameSDL) -> Result<(), Box<dyn error::Error>> {
     let mut event_pump = game.context.event_pump()?;
-    let mut cycles = 0;
+
+    // Emulation runs off the wall clock, so timers tick at 60 Hz whatever the display refresh rate
+    let clock = SystemClock::new();
+    // With VIP timing the scheduler counts machine cycles, starting at the original VIP's speed
+    let timing = game.cpu.timing();
+    let speed = match (&game.player, timing) {
+        (Some(player), _) => player.movie().cycles_per_second,
+        (None, Timing::Instruction) => game.instructions_per_second,
+        (None, Timing::Vip) => VIP_CYCLES_PER_FRAME * TIMER_HZ,
+    };
+    let mut scheduler = Scheduler::new(speed);
+    if game.run_cycles > 0 {
+        scheduler.set_instruction_limit(Some(game.run_cycles as u64));
+    }
+    show_speed(&mut game.canvas, timing, speed)?;
+    let mut rewinding = false;
+
+    // Anything that changes the CPU outside of the recorded input would throw a movie out of sync
+    let mut recording = game.record_path.as_ref().map(|_| Movie::record(&game.cpu, &game.rom, &scheduler));
+    let movie_locked = recording.is_some() || game.player.is_some();
 
     'gameloop: loop {
         for evt in event_pump.poll_iter() {
@@ -57,67 +174,173 @@ pub fn run_game(game: &mut GameSDL) -> Result<(), Box<dyn error::Error>> {
                 Event::Quit{..} | Event::KeyDown {keycode: Some(Keycode::Escape), ..} => {
                     break 'gameloop;
                 },
-                Event::KeyDown{keycode: Some(key), ..} => {
-                    if let Some(k) = key_to_button(key) {
-                        game.cpu.keypress(k, true)?;
+                // Speed up or slow down the CPU while running
+                Event::KeyDown{keycode: Some(Keycode::Equals | Keycode::KpPlus | Keycode::Minus | Keycode::KpMinus | Keycode::F9 | Keycode::Backspace), ..}
+                    if movie_locked => {
+                    eprintln!("{}", MOVIE_LOCKED);
+                },
+                Event::KeyDown{keycode: Some(Keycode::Equals | Keycode::KpPlus), ..} => {
+                    let speed = faster(scheduler.cycles_per_second(), timing);
+                    scheduler.set_cycles_per_second(speed);
+                    show_speed(&mut game.canvas, timing, speed)?;
+                },
+                Event::KeyDown{keycode: Some(Keycode::Minus | Keycode::KpMinus), ..} => {
+                    let speed = slower(scheduler.cycles_per_second(), timing);
+                    scheduler.set_cycles_per_second(speed);
+                    show_speed(&mut game.canvas, timing, speed)?;
+                },
+                // Save states: F5 saves and F9 loads the current slot, F6 and F7 pick the slot
+                Event::KeyDown{keycode: Some(Keycode::F5), ..} => {
+                    match game.state_slots.save(&game.cpu) {
+                        Ok(()) => println!("Saved state to {}", game.state_slots.path().display()),
+                        Err(e) => eprintln!("Failed to save state: {}", e),
                     }
                 },
-                Event::KeyUp{keycode: Some(key), ..} => {
+                Event::KeyDown{keycode: Some(Keycode::F9), ..} => {
+                    match game.state_slots.load(&mut game.cpu) {
+                        Ok(()) => println!("Loaded state from {}", game.state_slots.path().display()),
+                        Err(e) => eprintln!("Failed to load state: {}", e),
+                    }
+                },
+                Event::KeyDown{keycode: Some(Keycode::F6), ..} => {
+                    game.state_slots.previous_slot();
+                    println!("Save state slot {}", game.state_slots.slot());
+                },
+                Event::KeyDown{keycode: Some(Keycode::F7), ..} => {
+                    game.state_slots.next_slot();
+                    println!("Save state slot {}", game.state_slots.slot());
+                },
+                // Debugger: F10 opens and closes it, the other keys only work while it's open
+                Event::KeyDown{keycode: Some(Keycode::F10), ..} => {
+                    game.debugger.toggle();
+                },
+                Event::KeyDown{keycode: Some(Keycode::Space), ..} if game.debugger.visible() => {
+                    game.debugger.pause_or_resume();
+                },
+                Event::KeyDown{keycode: Some(Keycode::N), ..} if game.debugger.visible() => {
+                    game.debugger.step();
+                },
+                Event::KeyDown{keycode: Some(Keycode::O), ..} if game.debugger.visible() => {
+                    game.debugger.step_over(&game.cpu);
+                },
+                Event::KeyDown{keycode: Some(Keycode::U), ..} if game.debugger.visible() => {
+                    game.debugger.run_to_return(&game.cpu);
+                },
+                // Step backwards through recent frames while backspace is held
+                Event::KeyDown{keycode: Some(Keycode::Backspace), ..} => {
+                    rewinding = true;
+                },
+                Event::KeyUp{keycode: Some(Keycode::Backspace), ..} => {
+                    rewinding = false;
+                },
+                // Live input is ignored while a movie plays
+                Event::KeyDown{keycode: Some(key), ..} | Event::KeyUp{keycode: Some(key), ..} if game.player.is_none() => {
+                    let pressed = matches!(evt, Event::KeyDown{..});
                     if let Some(k) = key_to_button(key) {
-                        game.cpu.keypress(k, false)?;
+                        game.cpu.keypress(k, pressed)?;
+                        if let Some(movie) = recording.as_mut() {
+                            movie.record_key(scheduler.instructions_run(), k, pressed);
+                        }
                     }
-                }
+                },
                 _ => (),
             }
         }
 
-        if (cycles < game.run_cycles) || (game.run_cycles == 0) {
-            for _ in 0..game.ticks_per_frame {
-                game.cpu.cycle()?;
-
-                // If run_cycles is non-zero, run only that many cycles
-                if game.run_cycles > 0 {
-                    cycles += 1;
-                }
-
-                // If diplay buffer was updated, wait for next frame
-                if game.cpu.display_update_flag {
-                    game.cpu.display_update_flag = false;
-                    break;
-                }
+        if rewinding {
+            game.rewind.step_back(&mut game.cpu)?;
+            scheduler.pause();
+        } else if game.debugger.halted() {
+            scheduler.pause();
+        } else {
+            let mut before_instruction = |cpu: &mut CPU, n: u64| match game.player.as_mut() {
+                Some(player) => player.apply(cpu, n),
+                None => Ok(()),
+            };
+            let ticks = match game.debugger.stepping() {
+                true => game.debugger.update(&mut scheduler, &mut game.cpu, &mut before_instruction)?,
+                false => scheduler.update_with(&mut game.cpu, &clock, &mut before_instruction)?,
+            };
+            if ticks > 0 {
+                game.rewind.push(&game.cpu);
             }
         }
 
-        game.cpu.tick_timers();
-        draw_screen(&game.cpu, &mut game.canvas)?;
+        if let Some(beeper) = game.beeper.as_mut() {
+            beeper.update(&game.cpu);
+            beeper.set_paused(game.debugger.halted());
+        }
+        draw_screen(&game.cpu, &mut game.canvas, &game.palette)?;
+        if game.debugger.visible() {
+            game.debugger.draw(&game.cpu, &mut game.canvas)?;
+        }
+        game.canvas.present();
+    }
+
+    if let (Some(mut movie), Some(path)) = (recording, game.record_path.as_ref()) {
+        movie.finish(&game.cpu, &scheduler);
+        fs::write(path, movie.to_string())?;
+        println!("Saved movie to {}", path.display());
     }
 
     Ok(())
 }
 
-fn draw_screen(cpu: &CPU, canvas: &mut Canvas<Window>) -> Result<(), Box<dyn std::error::Error>> {
-    // Clear canvas with black
-    canvas.set_draw_color(Color::RGB(0, 0, 0));
+fn speed_limits(timing: Timing) -> (u32, u32) {
+    match timing {
+        Timing::Instruction => (MIN_SPEED, MAX_SPEED),
+        Timing::Vip => (MIN_VIP_SPEED, MAX_VIP_SPEED),
+    }
+}
+
+fn speed_step(speed: u32) -> u32 {
+    (speed / 100 * SPEED_STEP_PERCENT).max(1)
+}
+
+// A speed given on the command line can be outside the limits, so never step the wrong way to get back in them
+fn faster(speed: u32, timing: Timing) -> u32 {
+    let (_, max) = speed_limits(timing);
+    speed.saturating_add(speed_step(speed)).min(max).max(speed)
+}
+
+fn slower(speed: u32, timing: Timing) -> u32 {
+    let (min, _) = speed_limits(timing);
+    speed.saturating_sub(speed_step(speed)).max(min).min(speed)
+}
+
+fn show_speed(canvas: &mut Canvas<Window>, timing: Timing, speed: u32) -> Result<(), Box<dyn error::Error>> {
+    let unit = match timing {
+        Timing::Instruction => "instructions/s",
+        Timing::Vip => "cycles/s",
+    };
+    let title = format!("{} - {} {}", WINDOW_TITLE, speed, unit);
+    canvas.window_mut().set_title(&title)?;
+    Ok(())
+}
+
+fn draw_screen(cpu: &CPU, canvas: &mut Canvas<Window>, palette: &Palette) -> Result<(), Box<dyn std::error::Error>> {
+    // Clear canvas with the background color
+    canvas.set_draw_color(palette[0]);
     canvas.clear();
 
-    let screen_buffer = cpu.get_display();
-    
-    // Draw color white
-    canvas.set_draw_color(Color::RGB(255, 255, 255));
+    let display = cpu.get_display();
+
+    // Scale whatever resolution the core is in to fill the window
+    let scale = WINDOW_WIDTH / display.width() as u32;
 
-    // Draw rectangles as pixels
-    for (i, pixel) in screen_buffer.iter().enumerate() {
-        if *pixel {
+    // Draw rectangles as pixels, colored by which planes they are lit in
+    for (i, plane) in display.pixels().iter().enumerate() {
+        if *plane != 0 {
             // Get (x,y) from i and screen dimensions
-            let x = (i % SCREEN_WIDTH) as u32;
-            let y = (i / SCREEN_WIDTH) as u32;
+            let x = (i % display.width()) as u32;
+            let y = (i / display.width()) as u32;
 
             // Draw rectangle
-            canvas.fill_rect(Rect::new((x * DISPLAY_SCALE) as i32, (y * DISPLAY_SCALE) as i32, DISPLAY_SCALE, DISPLAY_SCALE))?;
+            canvas.set_draw_color(palette[*plane as usize]);
+            canvas.fill_rect(Rect::new((x * scale) as i32, (y * scale) as i32, scale, scale))?;
         }
     }
 
-    canvas.present();
     Ok(())
 }
 
@@ -1,25 +1,133 @@
 use std::{env, process};
-use frontend_sdl;
+use core::{cli, Movie, Quirks, Timing};
+use frontend_sdl::Options;
 use std::fs::File;
 use std::io::Read;
+use std::path::{Path, PathBuf};
+
+const USAGE: &str = "Usage: cargo run </path/to/rom or .8o source> <instructions per second> <cycles to run (optional)> [--quirks <vip|chip48|schip1.0|schip1.1|xochip>] [--timing <instruction|vip>] [--palette <rrggbb,rrggbb,rrggbb,rrggbb>] [--pitch <hz>] [--volume <0.0-1.0>] [--rewind <MiB>] [--seed <n>] [--record <movie>] [--play <movie>] [--debug]";
 
 fn main() {
     // Collect command line arguments
-    let args: Vec<_> = env::args().collect();
+    let mut args: Vec<_> = env::args().collect();
+    let mut options = Options::default();
+
+    // Pull out optional flags, leaving only the positional arguments
+    if let Some(name) = cli::take_flag(&mut args, "--quirks", USAGE) {
+        options.quirks = match Quirks::from_name(&name) {
+            Some(q) => q,
+            None => {
+                eprintln!("Unknown quirk profile: {}", name);
+                process::exit(1);
+            }
+        };
+    }
+
+    if let Some(timing) = cli::take_flag(&mut args, "--timing", USAGE) {
+        options.timing = match Timing::from_name(&timing) {
+            Some(t) => t,
+            None => {
+                eprintln!("Unknown timing model: {}", timing);
+                process::exit(1);
+            }
+        };
+    }
+
+    if let Some(colors) = cli::take_flag(&mut args, "--palette", USAGE) {
+        options.palette = match frontend_sdl::parse_palette(&colors) {
+            Ok(palette) => palette,
+            Err(err) => {
+                eprintln!("Failed to parse palette: {}", err);
+                process::exit(1);
+            }
+        };
+    }
+
+    if let Some(pitch) = cli::take_flag(&mut args, "--pitch", USAGE) {
+        options.pitch = match pitch.parse::<f32>() {
+            Ok(hz) if hz > 0.0 => hz,
+            _ => {
+                eprintln!("Failed to parse pitch: {}", pitch);
+                process::exit(1);
+            }
+        };
+    }
+
+    if let Some(volume) = cli::take_flag(&mut args, "--volume", USAGE) {
+        options.volume = match volume.parse::<f32>() {
+            Ok(v) if (0.0..=1.0).contains(&v) => v,
+            _ => {
+                eprintln!("Failed to parse volume: {}", volume);
+                process::exit(1);
+            }
+        };
+    }
+
+    if let Some(rewind) = cli::take_flag(&mut args, "--rewind", USAGE) {
+        options.rewind_bytes = match rewind.parse::<usize>() {
+            Ok(mib) => mib * 1024 * 1024,
+            Err(err) => {
+                eprintln!("Failed to parse rewind memory: {}", err);
+                process::exit(1);
+            }
+        };
+    }
+
+    if let Some(seed) = cli::take_flag(&mut args, "--seed", USAGE) {
+        options.seed = match seed.parse::<u64>() {
+            Ok(seed) => Some(seed),
+            Err(err) => {
+                eprintln!("Failed to parse seed: {}", err);
+                process::exit(1);
+            }
+        };
+    }
+
+    if let Some(path) = cli::take_flag(&mut args, "--record", USAGE) {
+        options.record_path = Some(PathBuf::from(path));
+    }
+
+    // Playing a movie overrides the settings it was recorded with
+    if let Some(path) = cli::take_flag(&mut args, "--play", USAGE) {
+        let movie = match std::fs::read_to_string(&path).map_err(|e| e.to_string())
+            .and_then(|text| Movie::parse(&text).map_err(|e| e.to_string())) {
+            Ok(movie) => movie,
+            Err(err) => {
+                eprintln!("Failed to read movie {}: {}", path, err);
+                process::exit(1);
+            }
+        };
+        options.quirks = movie.quirks;
+        options.timing = movie.timing;
+        options.playback = Some(movie);
+    }
+
+    // Start paused in the debugger
+    options.debug = cli::take_switch(&mut args, "--debug");
+
+    if options.record_path.is_some() && options.playback.is_some() {
+        eprintln!("Can't record and play a movie at the same time");
+        process::exit(1);
+    }
+
     if 3 > args.len() || args.len() > 4 {
-        eprintln!("Usage: cargo run </path/to/rom> <cycles per frame> <cycles to run (optional)> ");
+        eprintln!("{}", USAGE);
         process::exit(1);
     }
 
-    let cycles_per_frame = match args[2].clone().parse::<u32>() {
-        Ok(n) => n,
+    options.instructions_per_second = match args[2].clone().parse::<u32>() {
+        Ok(n) if n > 0 => n,
+        Ok(_) => {
+            eprintln!("Instructions per second must be greater than 0");
+            process::exit(1);
+        }
         Err(err) => {
             eprintln!("Failed to parse arguments: {}", err);
             process::exit(1);
         }
     };
 
-    let cycles = match args.len() {
+    options.run_cycles = match args.len() {
         3 => 0,
         4 => match args[3].clone().parse::<u32>() {
             Ok(n) => n,
@@ -29,11 +137,16 @@ fn main() {
             }
         },
         _ => {
-            eprintln!("Usage: cargo run </path/to/rom> <cycles per frame> <cycles to run (optional)> ");
+            eprintln!("{}", USAGE);
             process::exit(1);
         }
     };
 
+    if options.record_path.is_some() && options.run_cycles > 0 {
+        eprintln!("Can't record a movie with a cycle limit");
+        process::exit(1);
+    }
+
     println!("Loading file: {}", args[1].clone());
 
     // Open ROM file
@@ -51,8 +164,20 @@ fn main() {
         process::exit(1);
     }
 
+    // Octo source is compiled before it runs
+    if Path::new(&args[1]).extension().is_some_and(|extension| extension == "8o") {
+        let source = String::from_utf8_lossy(&rom_buffer).into_owned();
+        rom_buffer = match octo::compile(&source, Path::new(&args[1])) {
+            Ok(rom) => rom,
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            },
+        };
+    }
+
     // Create fontend instance
-    let mut game_sdl = match frontend_sdl::init_frontend(rom_buffer, cycles_per_frame, cycles) {
+    let mut game_sdl = match frontend_sdl::init_frontend(Path::new(&args[1]), rom_buffer, &options) {
         Ok(game) => game,
         Err(e) => {
             eprintln!("Error starting frontend: {}", e);
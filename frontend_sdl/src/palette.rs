@@ -0,0 +1,58 @@
+use core::NUM_COLORS;
+use sdl2::pixels::Color;
+
+// Colors for each plane index reported by the core: background, plane 1, plane 2, both planes
+pub type Palette = [Color; NUM_COLORS];
+
+pub const DEFAULT_PALETTE: Palette = [
+    Color::RGB(0, 0, 0),
+    Color::RGB(255, 255, 255),
+    Color::RGB(170, 170, 170),
+    Color::RGB(85, 85, 85),
+];
+
+// Parse a comma separated list of hex colors, e.g. "000000,ffffff,aaaaaa,555555"
+pub fn parse_palette(text: &str) -> Result<Palette, String> {
+    let mut palette = DEFAULT_PALETTE;
+    let colors: Vec<&str> = text.split(',').map(|c| c.trim().trim_start_matches('#')).collect();
+    if colors.len() != NUM_COLORS {
+        return Err(format!("expected {} colors, got {}", NUM_COLORS, colors.len()));
+    }
+
+    for (i, color) in colors.iter().enumerate() {
+        let rgb = match u32::from_str_radix(color, 16) {
+            Ok(rgb) if color.len() == 6 && color.chars().all(|c| c.is_ascii_hexdigit()) => rgb,
+            _ => return Err(format!("invalid color: {}", color)),
+        };
+        palette[i] = Color::RGB((rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8);
+    }
+
+    Ok(palette)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_palettes() {
+        let palette = parse_palette("102030,FFFFFF,#aabbcc, 000000").unwrap();
+        assert_eq!(palette, [
+            Color::RGB(0x10, 0x20, 0x30),
+            Color::RGB(0xFF, 0xFF, 0xFF),
+            Color::RGB(0xAA, 0xBB, 0xCC),
+            Color::RGB(0x00, 0x00, 0x00),
+        ]);
+        assert_eq!(parse_palette("000000,ffffff,aaaaaa,555555").unwrap(), DEFAULT_PALETTE);
+    }
+
+    #[test]
+    fn invalid_palettes() {
+        assert_eq!(parse_palette("000000,ffffff"), Err("expected 4 colors, got 2".to_string()));
+        assert_eq!(parse_palette("000000,ffffff,aaaaaa,555555,123456"), Err("expected 4 colors, got 5".to_string()));
+        assert_eq!(parse_palette("000000,ffffff,aaaaaa,55555g"), Err("invalid color: 55555g".to_string()));
+        assert_eq!(parse_palette("000000,ffffff,aaaaaa,fff"), Err("invalid color: fff".to_string()));
+        assert_eq!(parse_palette("000000,ffffff,aaaaaa,"), Err("invalid color: ".to_string()));
+        assert_eq!(parse_palette("000000,ffffff,aaaaaa,+fffff"), Err("invalid color: +fffff".to_string()));
+    }
+}
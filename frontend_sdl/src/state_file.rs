@@ -0,0 +1,43 @@
+use core::CPU;
+use std::{error, fs, path::{Path, PathBuf}};
+
+pub const NUM_SLOTS: u8 = 10;
+
+// Numbered save state slots in files next to the ROM, e.g. game.ch8 -> game.state0 to game.state9
+pub struct StateSlots {
+    rom_path: PathBuf,
+    slot: u8,
+}
+
+impl StateSlots {
+    pub fn for_rom(rom_path: &Path) -> Self {
+        Self { rom_path: rom_path.to_path_buf(), slot: 0 }
+    }
+
+    pub fn slot(&self) -> u8 {
+        self.slot
+    }
+
+    pub fn next_slot(&mut self) {
+        self.slot = (self.slot + 1) % NUM_SLOTS;
+    }
+
+    pub fn previous_slot(&mut self) {
+        self.slot = (self.slot + NUM_SLOTS - 1) % NUM_SLOTS;
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.rom_path.with_extension(format!("state{}", self.slot))
+    }
+
+    pub fn save(&self, cpu: &CPU) -> Result<(), Box<dyn error::Error>> {
+        fs::write(self.path(), cpu.save_state())?;
+        Ok(())
+    }
+
+    pub fn load(&self, cpu: &mut CPU) -> Result<(), Box<dyn error::Error>> {
+        let state = fs::read(self.path())?;
+        cpu.load_state(&state)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,90 @@
+use core::{Display, NUM_COLORS};
+
+// Background, plane 1, plane 2 and both planes, matching the SDL frontend's default palette
+pub const DEFAULT_COLORS: [[u8; 3]; NUM_COLORS] = [
+    [0x00, 0x00, 0x00],
+    [0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+];
+
+// One character per pixel, by plane
+const ASCII_PIXELS: [char; NUM_COLORS] = ['.', '#', '+', '@'];
+
+// Text art of the display, one line per row
+pub fn to_ascii(display: &Display) -> String {
+    let mut text = String::with_capacity((display.width() + 1) * display.height());
+    for row in display.pixels().chunks(display.width()) {
+        text.extend(row.iter().map(|plane| ASCII_PIXELS[*plane as usize]));
+        text.push('\n');
+    }
+    text
+}
+
+// Plain (P1) PBM bitmap, where a pixel lit in any plane is black
+pub fn to_pbm(display: &Display) -> String {
+    let mut text = format!("P1\n{} {}\n", display.width(), display.height());
+    for row in display.pixels().chunks(display.width()) {
+        let bits: Vec<&str> = row.iter().map(|plane| if *plane != 0 { "1" } else { "0" }).collect();
+        text.push_str(&bits.join(" "));
+        text.push('\n');
+    }
+    text
+}
+
+// RGB PNG at one image pixel per display pixel
+pub fn to_png(display: &Display, colors: &[[u8; 3]; NUM_COLORS]) -> Result<Vec<u8>, png::EncodingError> {
+    let mut data = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut data, display.width() as u32, display.height() as u32);
+        encoder.set_color(png::ColorType::Rgb);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        let pixels: Vec<u8> = display.pixels().iter().flat_map(|plane| colors[*plane as usize]).collect();
+        writer.write_image_data(&pixels)?;
+    }
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{CPU, SCREEN_HEIGHT, SCREEN_WIDTH};
+
+    // Draw the font's 0 in the top left corner
+    fn zero_on_screen() -> CPU {
+        let mut cpu = CPU::new();
+        assert!(cpu.load_rom_from_buffer(&[0xD0, 0x05]).is_ok());
+        assert!(cpu.cycle().is_ok());
+        cpu
+    }
+
+    #[test]
+    fn ascii() {
+        let text = to_ascii(zero_on_screen().get_display());
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), SCREEN_HEIGHT);
+        assert_eq!(&lines[0][..8], "####....");
+        assert_eq!(&lines[1][..8], "#..#....");
+        assert!(lines[5].chars().all(|c| c == '.'));
+    }
+
+    #[test]
+    fn pbm() {
+        let text = to_pbm(zero_on_screen().get_display());
+        assert!(text.starts_with("P1\n64 32\n1 1 1 1 0 0 0 0 0"));
+        assert_eq!(text.lines().count(), SCREEN_HEIGHT + 2);
+    }
+
+    #[test]
+    fn png() {
+        let data = to_png(zero_on_screen().get_display(), &DEFAULT_COLORS).unwrap();
+        let decoder = png::Decoder::new(data.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((info.width, info.height), (SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32));
+        assert_eq!(pixels[..6], [0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]);
+        assert_eq!(pixels[12..15], [0x00, 0x00, 0x00]);
+    }
+}
@@ -0,0 +1,91 @@
+mod image;
+
+use core::{CoreError, Movie, MoviePlayer, CPU};
+
+pub use image::{to_ascii, to_pbm, to_png, DEFAULT_COLORS};
+
+// Where a headless run stops
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Instructions(u64),
+    /// 60 Hz timer ticks
+    Frames(u64),
+    /// The end of the movie, checking the final state against the recording
+    MovieEnd,
+}
+
+// Run a ROM without a window, with the settings and input from a movie, as fast as possible.
+// Movies recorded by the SDL frontend can be used as they are, and scripted input can be built
+// with Movie::record() and Movie::record_key().
+// pokes are (address, value) pairs written to memory after the ROM is loaded, e.g. test suite options at 0x1FF.
+pub fn run(rom: &[u8], movie: &Movie, limit: Limit, pokes: &[(u16, u8)]) -> Result<CPU, CoreError> {
+    if limit == Limit::MovieEnd {
+        // Recorded movies start from the plain ROM, so changing memory would throw them out of sync
+        if !pokes.is_empty() {
+            return Err(CoreError::MovieError { line: 0, reason: "memory can't be changed when replaying to the end".to_string() });
+        }
+        return movie.replay(rom);
+    }
+
+    let (mut cpu, mut scheduler) = movie.start(rom)?;
+    for (address, value) in pokes {
+        cpu.write_memory(*address, &[*value])?;
+    }
+    let mut player = MoviePlayer::new(movie.clone());
+    let done = |scheduler: &core::Scheduler| match limit {
+        Limit::Instructions(n) => scheduler.instructions_run() >= n,
+        Limit::Frames(n) => scheduler.ticks_run() >= n,
+        Limit::MovieEnd => true,
+    };
+
+    while !done(&scheduler) {
+        scheduler.step(&mut cpu, &mut |cpu, n| player.apply(cpu, n))?;
+    }
+    Ok(cpu)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{Quirks, Scheduler};
+
+    // Adds 1 to v0 while key 5 is held, and loops
+    const ROM: [u8; 8] = [0x65, 0x05, 0xE5, 0xA1, 0x70, 0x01, 0x12, 0x00];
+
+    fn movie() -> Movie {
+        let mut cpu = CPU::with_quirks(Quirks::SCHIP_1_1);
+        cpu.seed_random(0);
+        assert!(cpu.load_rom_from_buffer(&ROM).is_ok());
+        Movie::record(&cpu, &ROM, &Scheduler::new(600))
+    }
+
+    #[test]
+    fn instruction_limit() {
+        let cpu = run(&ROM, &movie(), Limit::Instructions(40), &[]).unwrap();
+        assert_eq!(cpu.quirks(), Quirks::SCHIP_1_1);
+        assert_eq!(cpu.v_register(0), 0);
+    }
+
+    #[test]
+    fn scripted_keys() {
+        let mut movie = movie();
+        movie.record_key(8, 5, true);
+        movie.record_key(24, 5, false);
+        // Held from the 9th instruction to the 24th, which covers four trips round the loop
+        let cpu = run(&ROM, &movie, Limit::Frames(60), &[]).unwrap();
+        assert_eq!(cpu.v_register(0), 4);
+    }
+
+    #[test]
+    fn pokes() {
+        // The first instruction is overwritten with 60FF
+        let cpu = run(&ROM, &movie(), Limit::Instructions(1), &[(0x200, 0x60), (0x201, 0xFF)]).unwrap();
+        assert_eq!(cpu.v_register(0), 0xFF);
+        assert!(run(&ROM, &movie(), Limit::Instructions(1), &[(0x1000, 0)]).is_err());
+    }
+
+    #[test]
+    fn movie_end() {
+        assert!(run(&ROM, &movie(), Limit::MovieEnd, &[]).is_err());
+    }
+}
@@ -0,0 +1,116 @@
+use std::io::Write;
+use std::path::Path;
+use std::{env, fs, io};
+use core::cli::{self, fail};
+use core::{Movie, Quirks, Scheduler, Timing, CPU};
+use headless::Limit;
+
+const USAGE: &str = "Usage: chip8-headless <path/to/rom or .8o source> [--instructions <n> | --frames <n>] [--movie <file>] [--quirks <vip|chip48|schip1.0|schip1.1|xochip>] [--timing <instruction|vip>] [--speed <cycles per second>] [--seed <n>] [--key <instruction>:<key>:<down|up>]... [--poke <address>=<value>]... [--format <ascii|pbm|png>]";
+
+fn parse<T: std::str::FromStr>(value: &str, what: &str) -> T {
+    value.parse().unwrap_or_else(|_| fail(&format!("Failed to parse {}: {}", what, value)))
+}
+
+// "<instruction>:<key>:<down|up>", e.g. 1000:a:down
+fn parse_key(value: &str) -> (u64, usize, bool) {
+    let parts: Vec<&str> = value.split(':').collect();
+    let key = match parts.as_slice() {
+        [instruction, key, state] => {
+            let key = usize::from_str_radix(key, 16).ok().filter(|k| *k < 16);
+            let pressed = match *state {
+                "down" => Some(true),
+                "up" => Some(false),
+                _ => None,
+            };
+            instruction.parse().ok().zip(key).zip(pressed).map(|((i, k), p)| (i, k, p))
+        },
+        _ => None,
+    };
+    key.unwrap_or_else(|| fail(&format!("Failed to parse key: {}", value)))
+}
+
+// "<address>=<value>" in hex, e.g. 1ff=1
+fn parse_poke(value: &str) -> (u16, u8) {
+    let poke = value.split_once('=').and_then(|(address, byte)| {
+        u16::from_str_radix(address.trim_start_matches("0x"), 16).ok()
+            .zip(u8::from_str_radix(byte.trim_start_matches("0x"), 16).ok())
+    });
+    poke.unwrap_or_else(|| fail(&format!("Failed to parse poke: {}", value)))
+}
+
+fn main() {
+    let mut args: Vec<_> = env::args().collect();
+
+    let instructions = cli::take_flag(&mut args, "--instructions", USAGE).map(|n| parse::<u64>(&n, "instruction count"));
+    let frames = cli::take_flag(&mut args, "--frames", USAGE).map(|n| parse::<u64>(&n, "frame count"));
+    let movie_path = cli::take_flag(&mut args, "--movie", USAGE);
+    let quirks = cli::take_flag(&mut args, "--quirks", USAGE)
+        .map(|name| Quirks::from_name(&name).unwrap_or_else(|| fail(&format!("Unknown quirk profile: {}", name))));
+    let timing = cli::take_flag(&mut args, "--timing", USAGE)
+        .map(|name| Timing::from_name(&name).unwrap_or_else(|| fail(&format!("Unknown timing model: {}", name))));
+    let speed = cli::take_flag(&mut args, "--speed", USAGE).map(|n| parse::<u32>(&n, "speed"));
+    let seed = cli::take_flag(&mut args, "--seed", USAGE).map(|n| parse::<u64>(&n, "seed"));
+    let format = cli::take_flag(&mut args, "--format", USAGE).unwrap_or_else(|| "ascii".to_string());
+    let mut keys = Vec::new();
+    while let Some(key) = cli::take_flag(&mut args, "--key", USAGE) {
+        keys.push(parse_key(&key));
+    }
+    let mut pokes = Vec::new();
+    while let Some(poke) = cli::take_flag(&mut args, "--poke", USAGE) {
+        pokes.push(parse_poke(&poke));
+    }
+
+    if args.len() != 2 {
+        fail(USAGE);
+    }
+    // Octo source is compiled before it runs
+    let path = Path::new(&args[1]);
+    let rom = if path.extension().is_some_and(|extension| extension == "8o") {
+        let source = fs::read_to_string(path).unwrap_or_else(|e| fail(&format!("Error reading source: {}", e)));
+        octo::compile(&source, path).unwrap_or_else(|e| fail(&e.to_string()))
+    } else {
+        fs::read(path).unwrap_or_else(|e| fail(&format!("Error reading ROM: {}", e)))
+    };
+
+    let limit = match (instructions, frames, &movie_path) {
+        (Some(n), None, _) => Limit::Instructions(n),
+        (None, Some(n), _) => Limit::Frames(n),
+        (None, None, Some(_)) => Limit::MovieEnd,
+        _ => fail("Give one of --instructions or --frames, or a --movie to run to its end"),
+    };
+
+    // A movie brings its own settings, otherwise they come from the command line
+    let mut movie = match movie_path {
+        Some(path) => {
+            if quirks.is_some() || timing.is_some() || speed.is_some() || seed.is_some() {
+                fail("--quirks, --timing, --speed and --seed come from the movie when playing one");
+            }
+            let text = fs::read_to_string(&path).unwrap_or_else(|e| fail(&format!("Error reading movie: {}", e)));
+            Movie::parse(&text).unwrap_or_else(|e| fail(&format!("Error reading movie: {}", e)))
+        },
+        None => {
+            let mut cpu = CPU::with_quirks(quirks.unwrap_or_default());
+            cpu.set_timing(timing.unwrap_or_default());
+            cpu.seed_random(seed.unwrap_or(0));
+            Movie::record(&cpu, &rom, &Scheduler::new(speed.unwrap_or(700)))
+        },
+    };
+    for (instruction, key, pressed) in keys {
+        movie.record_key(instruction, key, pressed);
+    }
+    movie.keys.sort_by_key(|event| event.instruction);
+
+    let cpu = headless::run(&rom, &movie, limit, &pokes).unwrap_or_else(|e| fail(&format!("Error running ROM: {}", e)));
+
+    let display = cpu.get_display();
+    let output = match format.as_str() {
+        "ascii" => headless::to_ascii(display).into_bytes(),
+        "pbm" => headless::to_pbm(display).into_bytes(),
+        "png" => headless::to_png(display, &headless::DEFAULT_COLORS)
+            .unwrap_or_else(|e| fail(&format!("Error encoding PNG: {}", e))),
+        _ => fail(&format!("Unknown output format: {}", format)),
+    };
+    if let Err(e) = io::stdout().write_all(&output) {
+        fail(&format!("Error writing output: {}", e));
+    }
+}
@@ -0,0 +1,15 @@
+#!/bin/sh
+# Download the Timendus test suite ROMs used by tests/timendus.rs into tests/roms, to be committed there.
+# The suite is MIT licensed, see https://github.com/Timendus/chip8-test-suite/blob/main/LICENSE.
+# Set CHIP8_TEST_SUITE_URL to fetch from a fork or a pinned commit instead.
+set -e
+
+SUITE_URL="${CHIP8_TEST_SUITE_URL:-https://raw.githubusercontent.com/Timendus/chip8-test-suite/main/bin}"
+ROMS="1-chip8-logo.ch8 2-ibm-logo.ch8 3-corax+.ch8 4-flags.ch8 5-quirks.ch8 6-keypad.ch8 7-beep.ch8 8-scrolling.ch8"
+
+mkdir -p "$(dirname "$0")/roms"
+cd "$(dirname "$0")/roms"
+for rom in $ROMS; do
+    echo "Fetching $rom"
+    curl -fsSL -o "$rom" "$SUITE_URL/$rom"
+done
@@ -0,0 +1,159 @@
+// Conformance tests against Timendus's CHIP-8 test suite (https://github.com/Timendus/chip8-test-suite).
+// Each ROM runs headlessly and its final screen is compared with a golden image in tests/golden.
+//
+// The suite's ROMs (MIT licensed) are vendored in tests/roms by tests/fetch-roms.sh, or CHIP8_TEST_SUITE can
+// point at the suite's bin directory instead. Without either the tests are skipped, and once the ROMs are there
+// a missing ROM or golden image fails the test. Run with UPDATE_GOLDEN=1 to write the current screens as the
+// golden images, then check them by hand against the suite's documentation before committing them.
+
+use std::path::PathBuf;
+use std::{env, fs};
+
+use core::{Movie, Quirks, Scheduler, CPU};
+use headless::Limit;
+
+// Fast enough for every test to finish within its frames, display wait included
+const SPEED: u32 = 1000;
+// Most of the ROMs let the user pick what to test, and skip the menu if the choice is already at 0x1FF
+const PRESET_ADDRESS: u16 = 0x1FF;
+
+struct Case {
+    golden: &'static str,
+    rom: &'static str,
+    quirks: Quirks,
+    preset: Option<u8>,
+    frames: u64,
+    // (instructions run, key, pressed)
+    keys: &'static [(u64, usize, bool)],
+}
+
+// CHIP8_TEST_SUITE, or the vendored ROMs if there are any
+fn suite_dir() -> Option<PathBuf> {
+    if let Some(dir) = env::var_os("CHIP8_TEST_SUITE") {
+        return Some(PathBuf::from(dir));
+    }
+    let vendored = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/roms");
+    let has_roms = fs::read_dir(&vendored).into_iter().flatten()
+        .any(|entry| entry.is_ok_and(|entry| entry.path().extension().is_some_and(|extension| extension == "ch8")));
+    has_roms.then_some(vendored)
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(format!("{}.txt", name))
+}
+
+fn check(case: &Case) {
+    let Some(suite_dir) = suite_dir() else {
+        eprintln!("skipping {}: no test suite ROMs, run tests/fetch-roms.sh or set CHIP8_TEST_SUITE", case.golden);
+        return;
+    };
+    let rom_path = suite_dir.join(case.rom);
+    let rom = fs::read(&rom_path)
+        .unwrap_or_else(|_| panic!("no ROM at {}, run tests/fetch-roms.sh or set CHIP8_TEST_SUITE", rom_path.display()));
+
+    let mut cpu = CPU::with_quirks(case.quirks);
+    cpu.seed_random(0);
+    let mut movie = Movie::record(&cpu, &rom, &Scheduler::new(SPEED));
+    for (instruction, key, pressed) in case.keys {
+        movie.record_key(*instruction, *key, *pressed);
+    }
+    let pokes: Vec<(u16, u8)> = case.preset.map(|preset| (PRESET_ADDRESS, preset)).into_iter().collect();
+
+    let cpu = headless::run(&rom, &movie, Limit::Frames(case.frames), &pokes).unwrap();
+    let screen = headless::to_ascii(cpu.get_display());
+
+    let golden = golden_path(case.golden);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&golden, &screen).unwrap();
+        eprintln!("wrote {}", golden.display());
+        return;
+    }
+
+    let expected = fs::read_to_string(&golden)
+        .unwrap_or_else(|_| panic!("no golden image at {}, run with UPDATE_GOLDEN=1 to create it", golden.display()));
+    assert!(screen == expected, "{} doesn't match {}\nexpected:\n{}\nfound:\n{}", case.golden, golden.display(), expected, screen);
+}
+
+#[test]
+fn chip8_logo() {
+    check(&Case { golden: "chip8-logo", rom: "1-chip8-logo.ch8", quirks: Quirks::VIP, preset: None, frames: 60, keys: &[] });
+}
+
+#[test]
+fn ibm_logo() {
+    check(&Case { golden: "ibm-logo", rom: "2-ibm-logo.ch8", quirks: Quirks::VIP, preset: None, frames: 60, keys: &[] });
+}
+
+#[test]
+fn corax_plus() {
+    check(&Case { golden: "corax-plus", rom: "3-corax+.ch8", quirks: Quirks::VIP, preset: None, frames: 60, keys: &[] });
+}
+
+#[test]
+fn flags() {
+    check(&Case { golden: "flags", rom: "4-flags.ch8", quirks: Quirks::VIP, preset: None, frames: 120, keys: &[] });
+}
+
+// The quirks test presets are 1 for CHIP-8, 3 for XO-CHIP and 4 for the original (legacy) SUPER-CHIP
+#[test]
+fn quirks_chip8() {
+    check(&Case { golden: "quirks-chip8", rom: "5-quirks.ch8", quirks: Quirks::VIP, preset: Some(1), frames: 600, keys: &[] });
+}
+
+#[test]
+fn quirks_schip() {
+    check(&Case { golden: "quirks-schip", rom: "5-quirks.ch8", quirks: Quirks::SCHIP_1_1, preset: Some(4), frames: 600, keys: &[] });
+}
+
+#[test]
+fn quirks_xochip() {
+    check(&Case { golden: "quirks-xochip", rom: "5-quirks.ch8", quirks: Quirks::XO_CHIP, preset: Some(3), frames: 600, keys: &[] });
+}
+
+// The keypad test presets are 1 for EX9E, 2 for EXA1 and 3 for FX0A. Key times count executed
+// instructions, and a display wait leaves only a few of those per frame, so leave plenty of frames
+const KEYPAD_FRAMES: u64 = 1200;
+
+#[test]
+fn keypad_ex9e() {
+    let keys = &[(2000, 0x5, true), (4000, 0x5, false)];
+    check(&Case { golden: "keypad-ex9e", rom: "6-keypad.ch8", quirks: Quirks::VIP, preset: Some(1), frames: KEYPAD_FRAMES, keys });
+}
+
+#[test]
+fn keypad_exa1() {
+    let keys = &[(2000, 0x5, true), (4000, 0x5, false)];
+    check(&Case { golden: "keypad-exa1", rom: "6-keypad.ch8", quirks: Quirks::VIP, preset: Some(2), frames: KEYPAD_FRAMES, keys });
+}
+
+#[test]
+fn keypad_fx0a() {
+    let keys = &[(2000, 0x5, true), (4000, 0x5, false)];
+    check(&Case { golden: "keypad-fx0a", rom: "6-keypad.ch8", quirks: Quirks::VIP, preset: Some(3), frames: KEYPAD_FRAMES, keys });
+}
+
+#[test]
+fn beep() {
+    check(&Case { golden: "beep", rom: "7-beep.ch8", quirks: Quirks::VIP, preset: None, frames: 120, keys: &[] });
+}
+
+// The scrolling test presets are 1 for SUPER-CHIP lores, 2 for SUPER-CHIP hires, 3 for XO-CHIP lores and 4 for XO-CHIP hires
+#[test]
+fn scrolling_schip_lores() {
+    check(&Case { golden: "scrolling-schip-lores", rom: "8-scrolling.ch8", quirks: Quirks::SCHIP_1_1, preset: Some(1), frames: 300, keys: &[] });
+}
+
+#[test]
+fn scrolling_schip_hires() {
+    check(&Case { golden: "scrolling-schip-hires", rom: "8-scrolling.ch8", quirks: Quirks::SCHIP_1_1, preset: Some(2), frames: 300, keys: &[] });
+}
+
+#[test]
+fn scrolling_xochip_lores() {
+    check(&Case { golden: "scrolling-xochip-lores", rom: "8-scrolling.ch8", quirks: Quirks::XO_CHIP, preset: Some(3), frames: 300, keys: &[] });
+}
+
+#[test]
+fn scrolling_xochip_hires() {
+    check(&Case { golden: "scrolling-xochip-hires", rom: "8-scrolling.ch8", quirks: Quirks::XO_CHIP, preset: Some(4), frames: 300, keys: &[] });
+}
@@ -0,0 +1,159 @@
+use crate::token::Token;
+
+// Octo's compile time arithmetic, as used by :calc and { } values. Operators have no precedence and
+// evaluate right to left, so 2 * 3 + 1 is 8. Parentheses group as usual.
+pub(crate) fn evaluate<'a>(tokens: &'a [Token], end: &'a Token, lookup: &dyn Fn(&str) -> Option<f64>) -> Result<f64, (&'a Token, String)> {
+    let mut calc = Calc { tokens, end, position: 0, lookup };
+    let value = calc.expr()?;
+    match calc.tokens.get(calc.position) {
+        Some(token) => Err((token, format!("unexpected '{}'", token.text))),
+        None => Ok(value),
+    }
+}
+
+struct Calc<'a, 'b> {
+    tokens: &'a [Token],
+    end: &'a Token,
+    position: usize,
+    lookup: &'b dyn Fn(&str) -> Option<f64>,
+}
+
+impl<'a> Calc<'a, '_> {
+    fn next(&mut self) -> Result<&'a Token, (&'a Token, String)> {
+        let token = self.tokens.get(self.position).ok_or((self.end, "expression ends too soon".to_string()))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expr(&mut self) -> Result<f64, (&'a Token, String)> {
+        let left = self.term()?;
+        let Some(op) = self.tokens.get(self.position).filter(|token| is_binary(&token.text)) else {
+            return Ok(left);
+        };
+        self.position += 1;
+        let right = self.expr()?;
+        Ok(binary(&op.text, left, right))
+    }
+
+    fn term(&mut self) -> Result<f64, (&'a Token, String)> {
+        let token = self.next()?;
+        if token.text == "(" {
+            let value = self.expr()?;
+            let close = self.next()?;
+            if close.text != ")" {
+                return Err((close, format!("expected ')', found '{}'", close.text)));
+            }
+            return Ok(value);
+        }
+        if is_unary(&token.text) {
+            let operand = self.term()?;
+            return Ok(unary(&token.text, operand));
+        }
+        if let Some(value) = number(&token.text) {
+            return Ok(value as f64);
+        }
+        (self.lookup)(&token.text).ok_or((token, format!("unknown constant or label '{}'", token.text)))
+    }
+}
+
+fn is_binary(op: &str) -> bool {
+    matches!(op, "+" | "-" | "*" | "/" | "%" | "&" | "|" | "^" | "<<" | ">>" | "pow" | "min" | "max" | "<" | "<=" | "==" | "!=" | ">=" | ">")
+}
+
+fn binary(op: &str, left: f64, right: f64) -> f64 {
+    let (a, b) = (left as i64, right as i64);
+    let truth = |condition: bool| if condition { 1.0 } else { 0.0 };
+    match op {
+        "+" => left + right,
+        "-" => left - right,
+        "*" => left * right,
+        "/" => left / right,
+        "%" => left % right,
+        "&" => (a & b) as f64,
+        "|" => (a | b) as f64,
+        "^" => (a ^ b) as f64,
+        "<<" => a.wrapping_shl(b as u32) as f64,
+        ">>" => a.wrapping_shr(b as u32) as f64,
+        "pow" => left.powf(right),
+        "min" => left.min(right),
+        "max" => left.max(right),
+        "<" => truth(left < right),
+        "<=" => truth(left <= right),
+        "==" => truth(left == right),
+        "!=" => truth(left != right),
+        ">=" => truth(left >= right),
+        _ => truth(left > right),
+    }
+}
+
+fn is_unary(op: &str) -> bool {
+    matches!(op, "-" | "~" | "!" | "sin" | "cos" | "tan" | "exp" | "log" | "abs" | "sqrt" | "sign" | "ceil" | "floor")
+}
+
+fn unary(op: &str, value: f64) -> f64 {
+    match op {
+        "-" => -value,
+        "~" => !(value as i64) as f64,
+        "!" => if value == 0.0 { 1.0 } else { 0.0 },
+        "sin" => value.sin(),
+        "cos" => value.cos(),
+        "tan" => value.tan(),
+        "exp" => value.exp(),
+        "log" => value.ln(),
+        "abs" => value.abs(),
+        "sqrt" => value.sqrt(),
+        "sign" => if value == 0.0 { 0.0 } else { value.signum() },
+        "ceil" => value.ceil(),
+        _ => value.floor(),
+    }
+}
+
+// Decimal, 0x hexadecimal or 0b binary, optionally negative
+pub(crate) fn number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, text),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = digits.strip_prefix("0b").or_else(|| digits.strip_prefix("0B")) {
+        i64::from_str_radix(binary, 2).ok()?
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::token::tokenize;
+
+    fn calc(source: &str) -> Result<f64, String> {
+        let tokens = tokenize(source);
+        let end = Token { text: "}".to_string(), line: 1, column: source.len() + 1 };
+        let lookup = |name: &str| if name == "HERE" { Some(0x208 as f64) } else { None };
+        evaluate(&tokens, &end, &lookup).map_err(|(token, reason)| format!("{}: {}", token.column, reason))
+    }
+
+    #[test]
+    fn right_to_left() {
+        assert_eq!(calc("2 * 3 + 1"), Ok(8.0));
+        assert_eq!(calc("( 2 * 3 ) + 1"), Ok(7.0));
+        assert_eq!(calc("HERE - 0x200 >> 1"), Ok(0x108 as f64));
+        assert_eq!(calc("0b101 | 0x10 << 1"), Ok((0b101 | 0x20) as f64));
+        assert_eq!(calc("- 3 + 5"), Ok(2.0));
+        assert_eq!(calc("floor ( 7 / 2 )"), Ok(3.0));
+        assert_eq!(calc("floor 7 / 2"), Ok(3.5));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(calc("1 +"), Err("4: expression ends too soon".to_string()));
+        assert_eq!(calc("1 + nope"), Err("5: unknown constant or label 'nope'".to_string()));
+        assert_eq!(calc("1 2"), Err("3: unexpected '2'".to_string()));
+        assert_eq!(calc("( 1"), Err("4: expression ends too soon".to_string()));
+    }
+}
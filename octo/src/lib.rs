@@ -0,0 +1,866 @@
+mod calc;
+mod octo_error;
+mod token;
+
+use std::collections::HashMap;
+use std::f64::consts::{E, PI};
+use std::path::Path;
+
+use core::{Instruction, START_ADDRESS};
+
+use crate::token::{tokenize, Token};
+
+pub use crate::octo_error::OctoError;
+
+const MAX_ADDRESS: usize = 0xFFFF;
+// Stops macros that expand into themselves
+const MAX_MACRO_EXPANSIONS: usize = 10000;
+// Octo's < > <= >= comparisons use this alias as scratch space, ve unless the program changes it
+const COMPARE_TEMP: &str = "compare-temp";
+const DEFAULT_COMPARE_TEMP: u8 = 0xE;
+const FLAG_REGISTER: u8 = 0xF;
+
+/// Compile Octo source into a ROM that loads at 0x200. path names the source in errors.
+/// Unless `: main` comes before any code, the ROM starts with a jump to main like the reference compiler's.
+pub fn compile(source: &str, path: &Path) -> Result<Vec<u8>, OctoError> {
+    let mut compiler = Compiler::new(tokenize(source), path);
+    compiler.run()?;
+    Ok(compiler.rom)
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+// A label used before it was defined, filled in once every label is known
+enum Fixup {
+    // The low 12 bits of the instruction at this address
+    Address,
+    // The 16-bit word at this address, after an F000
+    Long,
+}
+
+// Jumps waiting for the address they go to
+enum Block {
+    Begin(usize),
+    Else(usize),
+}
+
+struct Loop {
+    start: usize,
+    whiles: Vec<usize>,
+}
+
+// Conditions as written after if and while, e.g. v0 == 5 or v1 -key
+struct Condition {
+    x: u8,
+    op: String,
+    rhs: Option<Rhs>,
+    token: Token,
+}
+
+enum Rhs {
+    Register(u8),
+    Byte(u8),
+}
+
+struct Compiler {
+    file: String,
+    tokens: Vec<Token>,
+    position: usize,
+    // Memory from 0x200 up to the highest address written
+    rom: Vec<u8>,
+    here: usize,
+    labels: HashMap<String, usize>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<(usize, Fixup, Token)>,
+    blocks: Vec<(Block, Token)>,
+    loops: Vec<(Loop, Token)>,
+    expansions: usize,
+    jump_to_main: bool,
+}
+
+impl Compiler {
+    fn new(tokens: Vec<Token>, path: &Path) -> Self {
+        Self {
+            file: path.display().to_string(),
+            tokens,
+            position: 0,
+            rom: Vec::new(),
+            here: START_ADDRESS as usize,
+            labels: HashMap::new(),
+            constants: HashMap::new(),
+            aliases: HashMap::from([(COMPARE_TEMP.to_string(), DEFAULT_COMPARE_TEMP)]),
+            macros: HashMap::new(),
+            fixups: Vec::new(),
+            blocks: Vec::new(),
+            loops: Vec::new(),
+            expansions: 0,
+            jump_to_main: true,
+        }
+    }
+
+    fn run(&mut self) -> Result<(), OctoError> {
+        // Room for the jump to main, given back if main comes before any code
+        self.emit(0)?;
+
+        while self.position < self.tokens.len() {
+            self.statement()?;
+        }
+
+        if let Some((_, token)) = self.blocks.pop() {
+            return Err(self.error(&token, "begin without a matching end"));
+        }
+        if let Some((_, token)) = self.loops.pop() {
+            return Err(self.error(&token, "loop without a matching again"));
+        }
+
+        for (address, fixup, token) in std::mem::take(&mut self.fixups) {
+            let Some(value) = self.labels.get(&token.text).map(|address| *address as f64).or_else(|| self.constant(&token.text)) else {
+                return Err(self.error(&token, format!("undefined label '{}'", token.text)));
+            };
+            match fixup {
+                Fixup::Address => {
+                    let nnn = self.check(&token, value.floor() as i64, 0, 0xFFF)? as u16;
+                    let word = self.word_at(address) & 0xF000 | nnn;
+                    self.write_word(address, word);
+                },
+                Fixup::Long => {
+                    let word = self.check(&token, value.floor() as i64, 0, MAX_ADDRESS as i64)? as u16;
+                    self.write_word(address, word);
+                },
+            }
+        }
+
+        if self.jump_to_main {
+            let Some(&main) = self.labels.get("main") else {
+                let start = Token { text: String::new(), line: 1, column: 1 };
+                return Err(self.error(&start, "no ': main' to start the program at"));
+            };
+            if main > 0xFFF {
+                return Err(self.error(&self.tokens[0].clone(), "main has to be below 0x1000"));
+            }
+            self.write_word(START_ADDRESS as usize, Instruction::Jp(main as u16).encode());
+        }
+
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), OctoError> {
+        let token = self.next()?;
+
+        match token.text.as_str() {
+            ":" => {
+                let name = self.name()?;
+                if self.labels.contains_key(&name.text) {
+                    return Err(self.error(&name, format!("'{}' is already defined", name.text)));
+                }
+                // Like the reference compiler, main right at the start takes the jump's place
+                if name.text == "main" && self.jump_to_main && self.here == START_ADDRESS as usize + 2 && self.rom.len() == 2 {
+                    self.rom.clear();
+                    self.here = START_ADDRESS as usize;
+                    self.jump_to_main = false;
+                }
+                self.labels.insert(name.text, self.here);
+            },
+            ":const" => {
+                let name = self.name()?;
+                let value = self.value()?;
+                self.constants.insert(name.text, value);
+            },
+            ":alias" => {
+                let name = self.name()?;
+                let x = self.register()?;
+                self.aliases.insert(name.text, x);
+            },
+            ":calc" => {
+                let name = self.name()?;
+                let brace = self.next()?;
+                if brace.text != "{" {
+                    return Err(self.error(&brace, format!("expected '{{', found '{}'", brace.text)));
+                }
+                let value = self.calc()?;
+                self.constants.insert(name.text, value);
+            },
+            ":macro" => self.define_macro()?,
+            ":org" => {
+                let address = self.value()?.floor() as i64;
+                self.here = self.check(&token, address, START_ADDRESS as i64, MAX_ADDRESS as i64)? as usize;
+            },
+            ":byte" => {
+                let byte = self.byte()?;
+                self.emit_byte(byte)?;
+            },
+            ":call" => {
+                let nnn = self.address(Fixup::Address)?;
+                self.instruction(Instruction::Call(nnn))?;
+            },
+            "return" | ";" => self.instruction(Instruction::Ret)?,
+            "clear" => self.instruction(Instruction::Cls)?,
+            "bcd" => {
+                let x = self.register()?;
+                self.instruction(Instruction::LdB(x))?;
+            },
+            "save" | "load" => {
+                let x = self.register()?;
+                let range = self.peek_is("-");
+                let save = token.text == "save";
+                let instruction = if range {
+                    self.position += 1;
+                    let y = self.register()?;
+                    if save { Instruction::SaveRange { x, y } } else { Instruction::LoadRange { x, y } }
+                } else if save {
+                    Instruction::LdIVx(x)
+                } else {
+                    Instruction::LdVxI(x)
+                };
+                self.instruction(instruction)?;
+            },
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.instruction(Instruction::Drw { x, y, n })?;
+            },
+            "jump" => {
+                let nnn = self.address(Fixup::Address)?;
+                self.instruction(Instruction::Jp(nnn))?;
+            },
+            "jump0" => {
+                let nnn = self.address(Fixup::Address)?;
+                self.instruction(Instruction::JpV0(nnn))?;
+            },
+            "native" => {
+                let nnn = self.address(Fixup::Address)?;
+                self.instruction(Instruction::Sys(nnn))?;
+            },
+            "scroll-down" => {
+                let n = self.nibble()?;
+                self.instruction(Instruction::ScrollDown(n))?;
+            },
+            "scroll-up" => {
+                let n = self.nibble()?;
+                self.instruction(Instruction::ScrollUp(n))?;
+            },
+            "scroll-left" => self.instruction(Instruction::ScrollLeft)?,
+            "scroll-right" => self.instruction(Instruction::ScrollRight)?,
+            "exit" => self.instruction(Instruction::Exit)?,
+            "lores" => self.instruction(Instruction::Lores)?,
+            "hires" => self.instruction(Instruction::Hires)?,
+            "plane" => {
+                let n = self.nibble()?;
+                self.instruction(Instruction::Plane(n))?;
+            },
+            "audio" => self.instruction(Instruction::Audio)?,
+            "saveflags" => {
+                let x = self.register()?;
+                self.instruction(Instruction::LdRVx(x))?;
+            },
+            "loadflags" => {
+                let x = self.register()?;
+                self.instruction(Instruction::LdVxR(x))?;
+            },
+            "delay" | "buzzer" | "pitch" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let instruction = match token.text.as_str() {
+                    "delay" => Instruction::LdDtVx(x),
+                    "buzzer" => Instruction::LdStVx(x),
+                    _ => Instruction::Pitch(x),
+                };
+                self.instruction(instruction)?;
+            },
+            "i" => self.index_assignment()?,
+            "if" => self.conditional()?,
+            "else" => {
+                let Some((Block::Begin(jump), _)) = self.blocks.pop() else {
+                    return Err(self.error(&token, "else without a matching begin"));
+                };
+                self.blocks.push((Block::Else(self.here), token));
+                self.instruction(Instruction::Jp(0))?;
+                self.patch_jump(jump);
+            },
+            "end" => {
+                let Some((Block::Begin(jump) | Block::Else(jump), _)) = self.blocks.pop() else {
+                    return Err(self.error(&token, "end without a matching begin"));
+                };
+                self.patch_jump(jump);
+            },
+            "loop" => self.loops.push((Loop { start: self.here, whiles: Vec::new() }, token)),
+            "while" => {
+                if self.loops.is_empty() {
+                    return Err(self.error(&token, "while outside of a loop"));
+                }
+                let condition = self.condition()?;
+                self.emit_condition(condition, true)?;
+                let jump = self.here;
+                self.instruction(Instruction::Jp(0))?;
+                if let Some((current, _)) = self.loops.last_mut() {
+                    current.whiles.push(jump);
+                }
+            },
+            "again" => {
+                let Some((finished, _)) = self.loops.pop() else {
+                    return Err(self.error(&token, "again without a matching loop"));
+                };
+                self.instruction(Instruction::Jp(finished.start as u16))?;
+                for jump in finished.whiles {
+                    self.patch_jump(jump);
+                }
+            },
+            _ if self.is_register(&token) => self.register_assignment(&token)?,
+            _ if self.macros.contains_key(&token.text) => self.expand_macro(&token)?,
+            text if calc::number(text).is_some() => {
+                // Bare numbers are data, e.g. sprites
+                self.position -= 1;
+                let byte = self.byte()?;
+                self.emit_byte(byte)?;
+            },
+            _ => {
+                // Anything else names a subroutine to call, which may be defined further down
+                self.position -= 1;
+                let nnn = self.address(Fixup::Address)?;
+                self.instruction(Instruction::Call(nnn))?;
+            },
+        }
+
+        Ok(())
+    }
+
+    fn index_assignment(&mut self) -> Result<(), OctoError> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            ":=" if self.peek_is("hex") || self.peek_is("bighex") => {
+                let big = self.next()?.text == "bighex";
+                let x = self.register()?;
+                self.instruction(if big { Instruction::LdHf(x) } else { Instruction::LdF(x) })
+            },
+            ":=" if self.peek_is("long") => {
+                self.position += 1;
+                let address = self.address(Fixup::Long)?;
+                self.instruction(Instruction::LongI)?;
+                self.emit(address)
+            },
+            ":=" => {
+                let nnn = self.address(Fixup::Address)?;
+                self.instruction(Instruction::LdI(nnn))
+            },
+            "+=" => {
+                let x = self.register()?;
+                self.instruction(Instruction::AddI(x))
+            },
+            _ => Err(self.error(&op, format!("expected ':=' or '+=' after i, found '{}'", op.text))),
+        }
+    }
+
+    fn register_assignment(&mut self, target: &Token) -> Result<(), OctoError> {
+        let x = self.register_of(target)?;
+        let op = self.next()?;
+        let source = self.peek().filter(|token| self.is_register(token)).cloned();
+
+        let instruction = match (op.text.as_str(), source) {
+            (":=", Some(source)) => {
+                self.position += 1;
+                Instruction::LdReg { x, y: self.register_of(&source)? }
+            },
+            (":=", None) if self.peek_is("random") => {
+                self.position += 1;
+                Instruction::Rnd { x, nn: self.byte()? }
+            },
+            (":=", None) if self.peek_is("key") => {
+                self.position += 1;
+                Instruction::LdVxK(x)
+            },
+            (":=", None) if self.peek_is("delay") => {
+                self.position += 1;
+                Instruction::LdVxDt(x)
+            },
+            (":=", None) => Instruction::LdByte { x, nn: self.byte()? },
+            ("+=", None) => Instruction::AddByte { x, nn: self.byte()? },
+            ("-=", None) => Instruction::AddByte { x, nn: self.byte()?.wrapping_neg() },
+            ("+=" | "-=" | "=-" | "|=" | "&=" | "^=" | ">>=" | "<<=", Some(source)) => {
+                self.position += 1;
+                let y = self.register_of(&source)?;
+                match op.text.as_str() {
+                    "+=" => Instruction::AddReg { x, y },
+                    "-=" => Instruction::Sub { x, y },
+                    "=-" => Instruction::Subn { x, y },
+                    "|=" => Instruction::Or { x, y },
+                    "&=" => Instruction::And { x, y },
+                    "^=" => Instruction::Xor { x, y },
+                    ">>=" => Instruction::Shr { x, y },
+                    _ => Instruction::Shl { x, y },
+                }
+            },
+            ("=-" | "|=" | "&=" | "^=" | ">>=" | "<<=", None) => {
+                let found = self.peek_token()?;
+                return Err(self.error(&found, format!("{} needs a register, found '{}'", op.text, found.text)));
+            },
+            _ => return Err(self.error(&op, format!("unknown operator '{}'", op.text))),
+        };
+
+        self.instruction(instruction)
+    }
+
+    fn conditional(&mut self) -> Result<(), OctoError> {
+        let condition = self.condition()?;
+        let keyword = self.next()?;
+        match keyword.text.as_str() {
+            "then" => self.emit_condition(condition, false),
+            "begin" => {
+                self.emit_condition(condition, true)?;
+                self.blocks.push((Block::Begin(self.here), keyword));
+                self.instruction(Instruction::Jp(0))
+            },
+            _ => Err(self.error(&keyword, format!("expected then or begin, found '{}'", keyword.text))),
+        }
+    }
+
+    fn condition(&mut self) -> Result<Condition, OctoError> {
+        let x = self.register()?;
+        let token = self.next()?;
+        let rhs = match token.text.as_str() {
+            "key" | "-key" => None,
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => match self.peek().filter(|next| self.is_register(next)).cloned() {
+                Some(register) => {
+                    self.position += 1;
+                    Some(Rhs::Register(self.register_of(&register)?))
+                },
+                None => Some(Rhs::Byte(self.byte()?)),
+            },
+            _ => return Err(self.error(&token, format!("expected a comparison, found '{}'", token.text))),
+        };
+        Ok(Condition { x, op: token.text.clone(), rhs, token })
+    }
+
+    // Emit instructions that skip the next one unless the condition holds. Negated, they skip it if it holds
+    fn emit_condition(&mut self, condition: Condition, negated: bool) -> Result<(), OctoError> {
+        let op = match (negated, condition.op.as_str()) {
+            (false, op) => op,
+            (true, "==") => "!=",
+            (true, "!=") => "==",
+            (true, "key") => "-key",
+            (true, "-key") => "key",
+            (true, "<") => ">=",
+            (true, ">") => "<=",
+            (true, ">=") => "<",
+            (true, _) => ">",
+        };
+        let x = condition.x;
+        let temp = self.aliases[COMPARE_TEMP];
+
+        let instructions = match (op, condition.rhs) {
+            ("key", _) => vec![Instruction::Sknp(x)],
+            ("-key", _) => vec![Instruction::Skp(x)],
+            ("==", Some(Rhs::Register(y))) => vec![Instruction::SneReg { x, y }],
+            ("==", Some(Rhs::Byte(nn))) => vec![Instruction::SneByte { x, nn }],
+            ("!=", Some(Rhs::Register(y))) => vec![Instruction::SeReg { x, y }],
+            ("!=", Some(Rhs::Byte(nn))) => vec![Instruction::SeByte { x, nn }],
+            (op, Some(rhs)) => {
+                // Compare through ve and the carry flag: ve := rhs, then subtract one from the other
+                let load = match rhs {
+                    Rhs::Register(y) => Instruction::LdReg { x: temp, y },
+                    Rhs::Byte(nn) => Instruction::LdByte { x: temp, nn },
+                };
+                let subtract = match op {
+                    ">" | "<=" => Instruction::Sub { x: temp, y: x },
+                    _ => Instruction::Subn { x: temp, y: x },
+                };
+                let skip = match op {
+                    ">" | "<" => Instruction::SeByte { x: FLAG_REGISTER, nn: 1 },
+                    _ => Instruction::SneByte { x: FLAG_REGISTER, nn: 1 },
+                };
+                vec![load, subtract, skip]
+            },
+            (_, None) => return Err(self.error(&condition.token, "comparison needs a value")),
+        };
+
+        for instruction in instructions {
+            self.instruction(instruction)?;
+        }
+        Ok(())
+    }
+
+    fn define_macro(&mut self) -> Result<(), OctoError> {
+        let name = self.name()?;
+        let mut args = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                break;
+            }
+            args.push(token.text);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 0;
+        loop {
+            let token = self.next()?;
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" if depth == 0 => break,
+                "}" => depth -= 1,
+                _ => (),
+            }
+            body.push(token);
+        }
+
+        self.macros.insert(name.text, Macro { args, body });
+        Ok(())
+    }
+
+    // Replace the macro and its arguments with its body, substituting arguments by name
+    fn expand_macro(&mut self, token: &Token) -> Result<(), OctoError> {
+        self.expansions += 1;
+        if self.expansions > MAX_MACRO_EXPANSIONS {
+            return Err(self.error(token, format!("macro '{}' expands too many times", token.text)));
+        }
+
+        let arg_count = self.macros[&token.text].args.len();
+        let mut values = Vec::with_capacity(arg_count);
+        for _ in 0..arg_count {
+            values.push(self.next()?);
+        }
+
+        let definition = &self.macros[&token.text];
+        let body: Vec<Token> = definition.body.iter().map(|body_token| {
+            match definition.args.iter().position(|arg| *arg == body_token.text) {
+                Some(i) => values[i].clone(),
+                None => body_token.clone(),
+            }
+        }).collect();
+
+        self.tokens.splice(self.position..self.position, body);
+        Ok(())
+    }
+
+    // Evaluate the tokens up to the closing brace, the opening one having been read already
+    fn calc(&mut self) -> Result<f64, OctoError> {
+        let start = self.position;
+        let Some(length) = self.tokens[start..].iter().position(|token| token.text == "}") else {
+            let token = self.tokens[start - 1].clone();
+            return Err(self.error(&token, "'{' without a matching '}'"));
+        };
+        self.position = start + length + 1;
+
+        let lookup = |name: &str| match name {
+            "HERE" => Some(self.here as f64),
+            "PI" => Some(PI),
+            "E" => Some(E),
+            _ => self.constant(name).or_else(|| self.labels.get(name).map(|address| *address as f64)),
+        };
+        let result = calc::evaluate(&self.tokens[start..start + length], &self.tokens[start + length], &lookup);
+        result.map_err(|(token, reason)| self.error(token, reason))
+    }
+
+    // A number, constant, label or { calc } that has to be known now
+    fn value(&mut self) -> Result<f64, OctoError> {
+        let token = self.next()?;
+        if token.text == "{" {
+            return self.calc();
+        }
+        if let Some(number) = calc::number(&token.text) {
+            return Ok(number as f64);
+        }
+        match self.constant(&token.text).or_else(|| self.labels.get(&token.text).map(|address| *address as f64)) {
+            Some(value) => Ok(value),
+            None => Err(self.error(&token, format!("expected a number or constant, found '{}'", token.text))),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, OctoError> {
+        let token = self.peek_token()?;
+        let value = self.value()?.floor() as i64;
+        Ok(self.check(&token, value, -0x80, 0xFF)? as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, OctoError> {
+        let token = self.peek_token()?;
+        let value = self.value()?.floor() as i64;
+        Ok(self.check(&token, value, 0, 0xF)? as u8)
+    }
+
+    // An address for the instruction about to be emitted. Labels that aren't defined yet are filled in at the end
+    fn address(&mut self, fixup: Fixup) -> Result<u16, OctoError> {
+        let token = self.peek_token()?;
+        let max = match fixup {
+            Fixup::Address => 0xFFF,
+            Fixup::Long => MAX_ADDRESS as i64,
+        };
+
+        let known = token.text == "{" || calc::number(&token.text).is_some() || self.constant(&token.text).is_some()
+            || self.labels.contains_key(&token.text);
+        if known {
+            let value = self.value()?.floor() as i64;
+            return Ok(self.check(&token, value, 0, max)? as u16);
+        }
+
+        let name = self.name()?;
+        let address = match fixup {
+            Fixup::Address => self.here,
+            Fixup::Long => self.here + 2,
+        };
+        self.fixups.push((address, fixup, name));
+        Ok(0)
+    }
+
+    fn register(&mut self) -> Result<u8, OctoError> {
+        let token = self.next()?;
+        self.register_of(&token)
+    }
+
+    fn register_of(&self, token: &Token) -> Result<u8, OctoError> {
+        match register_number(&token.text).or_else(|| self.aliases.get(&token.text).copied()) {
+            Some(x) => Ok(x),
+            None => Err(self.error(token, format!("expected a register, found '{}'", token.text))),
+        }
+    }
+
+    fn is_register(&self, token: &Token) -> bool {
+        register_number(&token.text).is_some() || self.aliases.contains_key(&token.text)
+    }
+
+    // A name for a label, constant, alias or macro
+    fn name(&mut self) -> Result<Token, OctoError> {
+        let token = self.next()?;
+        if calc::number(&token.text).is_some() || register_number(&token.text).is_some() || matches!(token.text.as_str(), "{" | "}" | "(" | ")") {
+            return Err(self.error(&token, format!("'{}' can't be used as a name", token.text)));
+        }
+        Ok(token)
+    }
+
+    fn constant(&self, name: &str) -> Option<f64> {
+        self.constants.get(name).copied()
+    }
+
+    fn next(&mut self) -> Result<Token, OctoError> {
+        let token = self.peek_token()?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.peek().is_some_and(|token| token.text == text)
+    }
+
+    fn peek_token(&self) -> Result<Token, OctoError> {
+        match self.peek() {
+            Some(token) => Ok(token.clone()),
+            None => {
+                let last = self.tokens.last().cloned().unwrap_or(Token { text: String::new(), line: 1, column: 1 });
+                Err(self.error(&last, "unexpected end of file"))
+            },
+        }
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), OctoError> {
+        let token = self.next()?;
+        if token.text != text {
+            return Err(self.error(&token, format!("expected '{}', found '{}'", text, token.text)));
+        }
+        Ok(())
+    }
+
+    fn check(&self, token: &Token, value: i64, min: i64, max: i64) -> Result<i64, OctoError> {
+        if value < min || value > max {
+            return Err(self.error(token, format!("{} doesn't fit, expected {} to 0x{:X}", value, min, max)));
+        }
+        Ok(value)
+    }
+
+    fn instruction(&mut self, instruction: Instruction) -> Result<(), OctoError> {
+        self.emit(instruction.encode())
+    }
+
+    fn emit(&mut self, word: u16) -> Result<(), OctoError> {
+        for byte in word.to_be_bytes() {
+            self.emit_byte(byte)?;
+        }
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), OctoError> {
+        if self.here > MAX_ADDRESS {
+            let token = self.tokens[self.position - 1].clone();
+            return Err(self.error(&token, "program doesn't fit in memory"));
+        }
+        let offset = self.here - START_ADDRESS as usize;
+        if offset >= self.rom.len() {
+            self.rom.resize(offset + 1, 0);
+        }
+        self.rom[offset] = byte;
+        self.here += 1;
+        Ok(())
+    }
+
+    fn word_at(&self, address: usize) -> u16 {
+        let offset = address - START_ADDRESS as usize;
+        (self.rom[offset] as u16) << 8 | self.rom[offset + 1] as u16
+    }
+
+    fn write_word(&mut self, address: usize, word: u16) {
+        let offset = address - START_ADDRESS as usize;
+        self.rom[offset..offset + 2].copy_from_slice(&word.to_be_bytes());
+    }
+
+    // Point the placeholder jump at address to here
+    fn patch_jump(&mut self, address: usize) {
+        self.write_word(address, Instruction::Jp(self.here as u16).encode());
+    }
+
+    fn error(&self, token: &Token, reason: impl Into<String>) -> OctoError {
+        OctoError { file: self.file.clone(), line: token.line, column: token.column, reason: reason.into() }
+    }
+}
+
+fn register_number(text: &str) -> Option<u8> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some('v' | 'V'), Some(x), None) => x.to_digit(16).map(|x| x as u8),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::{disassemble, Syntax};
+
+    fn octo(source: &str) -> Result<Vec<u8>, OctoError> {
+        compile(source, Path::new("test.8o"))
+    }
+
+    #[test]
+    fn main_first() {
+        let source = "
+            : main
+              v0 := 5
+              v1 := 0x0A
+              i := digit
+              sprite v0 v1 5
+              loop
+                v0 += 1
+                if v0 == 10 then v0 := 0
+              again
+            : digit
+              0xF0 0x90 0xF0 0x90 0xF0
+        ";
+        assert_eq!(octo(source).unwrap(), [
+            0x60, 0x05, 0x61, 0x0A, 0xA2, 0x10, 0xD0, 0x15, 0x70, 0x01, 0x40, 0x0A, 0x60, 0x00, 0x12, 0x08,
+            0xF0, 0x90, 0xF0, 0x90, 0xF0,
+        ]);
+    }
+
+    #[test]
+    fn jump_to_main() {
+        let rom = octo(": sub return\n: main sub jump main").unwrap();
+        assert_eq!(rom, [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02, 0x12, 0x04]);
+        assert_eq!(octo(": sub return").unwrap_err().reason, "no ': main' to start the program at");
+        // Directives don't write anything, so main still starts the ROM
+        assert_eq!(octo(":const N 1 :alias x v1 :macro m { }\n: main x := N").unwrap(), [0x61, 0x01]);
+        assert_eq!(octo(":byte 7 : main").unwrap(), [0x12, 0x03, 0x07]);
+    }
+
+    #[test]
+    fn blocks() {
+        let source = "
+            : main
+              if v1 > 3 begin
+                v2 := 1
+              else
+                v2 := 2
+              end
+              loop
+                while v0 != 4
+                v0 += 1
+              again
+        ";
+        let listing: Vec<String> = disassemble(&octo(source).unwrap(), START_ADDRESS, Syntax::Octo).into_iter().map(|line| line.text).collect();
+        assert_eq!(listing, [
+            "ve := 0x03", "ve -= v1", "if vf == 0x01 then", "jump 0x20C", "v2 := 0x01", "jump 0x20E", "v2 := 0x02",
+            "if v0 == 0x04 then", "jump 0x216", "v0 += 0x01", "jump 0x20E",
+        ]);
+    }
+
+    #[test]
+    fn comparisons() {
+        let listing = |source: &str| -> Vec<String> {
+            let rom = octo(&format!(": main {}", source)).unwrap();
+            disassemble(&rom, START_ADDRESS, Syntax::Classic).into_iter().map(|line| line.text).collect()
+        };
+        assert_eq!(listing("if v0 != v1 then"), ["SE V0, V1"]);
+        assert_eq!(listing("if v0 key then"), ["SKNP V0"]);
+        assert_eq!(listing("if v0 -key then"), ["SKP V0"]);
+        assert_eq!(listing("if v0 < v3 then"), ["LD VE, V3", "SUBN VE, V0", "SE VF, 0x01"]);
+        assert_eq!(listing("if v0 >= 7 then"), ["LD VE, 0x07", "SUBN VE, V0", "SNE VF, 0x01"]);
+        assert_eq!(listing("if v0 <= 7 then"), ["LD VE, 0x07", "SUB VE, V0", "SNE VF, 0x01"]);
+        assert_eq!(listing(":alias compare-temp v9 if v0 > v1 then"), ["LD V9, V1", "SUB V9, V0", "SE VF, 0x01"]);
+    }
+
+    #[test]
+    fn directives() {
+        let source = "
+            :const SPEED 3
+            :alias x v4
+            :macro move reg amount { reg += amount }
+            :calc DOUBLE { SPEED * 2 }
+            : main
+              move x SPEED
+              x -= DOUBLE
+              i := long data
+              :byte { DOUBLE + 1 }
+            :org 0x300
+            : data 1 2
+        ";
+        let rom = octo(source).unwrap();
+        assert_eq!(rom.len(), 0x102);
+        assert_eq!(rom[..9], [0x74, 0x03, 0x74, 0xFA, 0xF0, 0x00, 0x03, 0x00, 0x07]);
+        assert!(rom[9..0x100].iter().all(|byte| *byte == 0));
+        assert_eq!(rom[0x100..], [0x01, 0x02]);
+    }
+
+    #[test]
+    fn instructions() {
+        let source = "
+            : main
+              clear return bcd v1 save v2 load v3 save v1 - v4 load v5 - v2
+              delay := v1 buzzer := v2 pitch := v3 v4 := delay v5 := key v6 := random 0x0F
+              i := hex v1 i := bighex v2 i += v3 saveflags v4 loadflags v5
+              v1 := v2 v1 =- v2 v1 |= v2 v1 &= v2 v1 ^= v2 v1 >>= v2 v1 <<= v2
+              scroll-down 4 scroll-up 2 scroll-left scroll-right lores hires exit plane 3 audio
+              jump0 0x300 native 0x123 :call main
+        ";
+        let rom = octo(source).unwrap();
+        let words: Vec<u16> = rom.chunks(2).map(|word| (word[0] as u16) << 8 | word[1] as u16).collect();
+        assert_eq!(words, [
+            0x00E0, 0x00EE, 0xF133, 0xF255, 0xF365, 0x5142, 0x5523,
+            0xF115, 0xF218, 0xF33A, 0xF407, 0xF50A, 0xC60F,
+            0xF129, 0xF230, 0xF31E, 0xF475, 0xF585,
+            0x8120, 0x8127, 0x8121, 0x8122, 0x8123, 0x8126, 0x812E,
+            0x00C4, 0x00D2, 0x00FC, 0x00FB, 0x00FE, 0x00FF, 0x00FD, 0xF301, 0xF002,
+            0xB300, 0x0123, 0x2200,
+        ]);
+    }
+
+    #[test]
+    fn errors() {
+        let error = |source| octo(source).unwrap_err();
+        assert_eq!(error(": main\n  jump nowhere"), OctoError {
+            file: "test.8o".to_string(), line: 2, column: 8, reason: "undefined label 'nowhere'".to_string(),
+        });
+        assert_eq!(error(": main\nv0 := 256").column, 7);
+        assert_eq!(error(": main\nsprite v0 q 5").reason, "expected a register, found 'q'");
+        assert_eq!(error(": main\nif v0 == 1 begin").reason, "begin without a matching end");
+        assert_eq!(error(": main\nagain").reason, "again without a matching loop");
+        assert_eq!(error(": main\nv0 += 1 :calc x { 1 +").reason, "'{' without a matching '}'");
+        assert_eq!(error(": main\n: main").line, 2);
+        assert_eq!(error(":macro m { m }\n: main m").reason, "macro 'm' expands too many times");
+    }
+}
@@ -0,0 +1,7 @@
+use core::cli;
+
+const USAGE: &str = "Usage: chip8-octo <path/to/source.8o> [-o <path/to/rom>]";
+
+fn main() {
+    cli::compile_main(USAGE, octo::compile);
+}
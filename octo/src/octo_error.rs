@@ -0,0 +1,18 @@
+use std::fmt;
+
+// Where and why compilation failed. Lines and columns count from 1
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OctoError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub reason: String,
+}
+
+impl std::error::Error for OctoError {}
+
+impl fmt::Display for OctoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.file, self.line, self.column, self.reason)
+    }
+}
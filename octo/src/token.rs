@@ -0,0 +1,56 @@
+// Octo source is a stream of whitespace separated tokens. Braces and parentheses are tokens of their own,
+// and # starts a comment that runs to the end of the line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Token {
+    pub text: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+pub(crate) fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+
+    for (index, line) in source.lines().enumerate() {
+        let mut start: Option<usize> = None;
+        let chars: Vec<char> = line.chars().collect();
+        let mut push = |from: usize, to: usize| {
+            tokens.push(Token { text: chars[from..to].iter().collect(), line: index + 1, column: from + 1 });
+        };
+
+        for (i, &c) in chars.iter().enumerate() {
+            let separate = matches!(c, '{' | '}' | '(' | ')');
+            if c.is_whitespace() || separate || (c == '#' && start.is_none()) {
+                if let Some(from) = start.take() {
+                    push(from, i);
+                }
+                if c == '#' {
+                    break;
+                }
+                if separate {
+                    push(i, i + 1);
+                }
+            } else if start.is_none() {
+                start = Some(i);
+            }
+        }
+        if let Some(from) = start {
+            push(from, chars.len());
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        let tokens = tokenize(": main\n  v0 := 5 # set v0\n:calc x {2 * (y)}");
+        let texts: Vec<&str> = tokens.iter().map(|token| token.text.as_str()).collect();
+        assert_eq!(texts, [":", "main", "v0", ":=", "5", ":calc", "x", "{", "2", "*", "(", "y", ")", "}"]);
+        assert_eq!((tokens[3].line, tokens[3].column), (2, 6));
+        assert_eq!((tokens[10].line, tokens[10].column), (3, 14));
+    }
+}
@@ -0,0 +1,13 @@
+#!/bin/sh
+# Build a reference ROM next to every sample in tests/samples with the reference Octo compiler's
+# command line tool (c-octo's octo-cli, https://github.com/JohnEarnest/c-octo), for tests/reference.rs.
+# Set OCTO_CLI if it isn't on the PATH as octo-cli.
+set -e
+
+OCTO_CLI="${OCTO_CLI:-octo-cli}"
+
+cd "$(dirname "$0")/samples"
+for source in *.8o; do
+    echo "Building ${source%.8o}.ch8"
+    "$OCTO_CLI" "$source" "${source%.8o}.ch8"
+done
@@ -0,0 +1,64 @@
+// Compares this compiler's output with ROMs built by the reference Octo compiler (https://github.com/JohnEarnest/Octo).
+// Every .8o file in tests/samples, or in the directory OCTO_SAMPLES points at, has to compile, and has to match the
+// .ch8 file with the same name next to it byte for byte. The reference ROMs are built with tests/build-reference.sh.
+// The comparison is skipped while there are none, and once there are any, a sample without one fails.
+
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+use core::{disassemble, Syntax, START_ADDRESS};
+
+fn samples_dir() -> PathBuf {
+    match env::var_os("OCTO_SAMPLES") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/samples"),
+    }
+}
+
+// Listing of the first instruction that differs, since raw bytes are hard to read
+fn first_difference(found: &[u8], expected: &[u8]) -> String {
+    let found = disassemble(found, START_ADDRESS, Syntax::Octo);
+    let expected = disassemble(expected, START_ADDRESS, Syntax::Octo);
+    match found.iter().zip(&expected).find(|(found, expected)| found != expected) {
+        Some((found, expected)) => format!("expected:\n{}\nfound:\n{}", expected, found),
+        None => format!("lengths differ, expected {} lines and found {}", expected.len(), found.len()),
+    }
+}
+
+fn samples() -> Vec<PathBuf> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(samples_dir()).unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "8o"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty(), "no samples in {}", samples_dir().display());
+    paths
+}
+
+fn compile(path: &Path) -> Vec<u8> {
+    let source = fs::read_to_string(path).unwrap();
+    octo::compile(&source, path).unwrap_or_else(|err| panic!("{}", err))
+}
+
+#[test]
+fn samples_compile() {
+    for path in samples() {
+        compile(&path);
+    }
+}
+
+#[test]
+fn samples_match_reference() {
+    let samples = samples();
+    if !samples.iter().any(|path| path.with_extension("ch8").exists()) {
+        eprintln!("skipping: no reference ROMs in {}, build them with tests/build-reference.sh", samples_dir().display());
+        return;
+    }
+    for path in samples {
+        let rom = compile(&path);
+        let reference = path.with_extension("ch8");
+        let expected = fs::read(&reference)
+            .unwrap_or_else(|_| panic!("no reference ROM at {}, build it with tests/build-reference.sh", reference.display()));
+        assert!(rom == expected, "{} doesn't match the reference compiler\n{}", path.display(), first_difference(&rom, &expected));
+    }
+}